@@ -0,0 +1,95 @@
+//! The `include_ym!` macro embedding YM-files at compile time.
+//!
+//! ```ignore
+//! use ym_file_parser::embed::StaticYmSong;
+//! use ym_file_parser_macros::include_ym;
+//!
+//! static SONG: StaticYmSong = include_ym!("music/song.ym");
+//!
+//! let mut player = SONG.player();
+//! ```
+//!
+//! The file is parsed with [ym_file_parser::parse_file] during compilation and the macro expands
+//! to a [StaticYmSong] expression with the frames and `DIGI-DRUM` samples as static data, so
+//! they are placed in the read-only memory of the program image and played from there.
+//!
+//! [StaticYmSong]: ym_file_parser::embed::StaticYmSong
+use std::path::PathBuf;
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, LitStr};
+
+use ym_file_parser::{parse_file, YmSong};
+
+/// Parses the YM-file at the given path and expands to a `StaticYmSong` expression.
+///
+/// The path is relative to the directory of the package's `Cargo.toml`. Any error while reading
+/// or parsing the file is reported as a compile error.
+#[proc_macro]
+pub fn include_ym(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    let mut path = PathBuf::from(lit.value());
+    if path.is_relative() {
+        if let Some(dir) = std::env::var_os("CARGO_MANIFEST_DIR") {
+            path = PathBuf::from(dir).join(path);
+        }
+    }
+    match parse_file(&path) {
+        Ok(song) => {
+            let path = path.to_string_lossy();
+            static_song(&song, &path).into()
+        }
+        Err(err) => {
+            let message = format!("{}: {}", path.display(), err);
+            syn::Error::new(lit.span(), message).to_compile_error().into()
+        }
+    }
+}
+
+fn static_song(song: &YmSong, path: &str) -> TokenStream2 {
+    let version = format_ident!("{}", format!("{:?}", song.version));
+    let song_attrs = song.song_attrs.bits();
+    let title = &song.title;
+    let author = &song.author;
+    let comments = &song.comments;
+    let program = &song.program;
+    let tracker = &song.tracker;
+    let year = song.year;
+    let chip_type = format_ident!("{}", format!("{:?}", song.chip_type));
+    let stereo = format_ident!("{}", format!("{:?}", song.stereo));
+    let chipset_frequency = song.chipset_frequency;
+    let frame_frequency = song.frame_frequency;
+    let loop_frame = song.loop_frame;
+    let frames = song.frames.iter().map(|frame| {
+        let data = frame.data;
+        quote! { ::ym_file_parser::YmFrame { data: [#(#data),*] } }
+    });
+    let dd_samples = &song.dd_samples[..];
+    let dd_samples_ends = song.dd_samples_ends;
+    quote! {
+        {
+            // rebuild when the file changes
+            const _: &[u8] = include_bytes!(#path);
+            ::ym_file_parser::embed::StaticYmSong {
+                version: ::ym_file_parser::YmVersion::#version,
+                song_attrs: ::ym_file_parser::flags::SongAttributes::from_bits_retain(#song_attrs),
+                title: #title,
+                author: #author,
+                comments: #comments,
+                program: #program,
+                tracker: #tracker,
+                year: #year,
+                chip_type: ::ym_file_parser::ChipType::#chip_type,
+                stereo: ::ym_file_parser::StereoLayout::#stereo,
+                chipset_frequency: #chipset_frequency,
+                frame_frequency: #frame_frequency,
+                loop_frame: #loop_frame,
+                frames: &[#(#frames),*],
+                dd_samples: &[#(#dd_samples),*],
+                dd_samples_ends: [#(#dd_samples_ends),*],
+            }
+        }
+    }
+}
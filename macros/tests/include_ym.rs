@@ -0,0 +1,26 @@
+use ym_file_parser::embed::StaticYmSong;
+use ym_file_parser_macros::include_ym;
+
+static SONG: StaticYmSong = include_ym!("tests/song.ym");
+
+#[test]
+fn include_ym_works() {
+    let song = ym_file_parser::parse_file(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/song.ym")).unwrap();
+    assert_eq!(SONG.version, song.version);
+    assert_eq!((SONG.title, SONG.author, SONG.comments), ("Test tune", "Anonymous", "include_ym! test"));
+    assert_eq!((SONG.chipset_frequency, SONG.frame_frequency, SONG.loop_frame), (2_000_000, 50, 2));
+    assert_eq!(SONG.frames.len(), song.frames.len());
+    for (a, b) in SONG.frames.iter().zip(song.frames.iter()) {
+        assert_eq!(a.data, b.data);
+    }
+    assert_eq!(SONG.to_song().song_duration(), song.song_duration());
+    let mut song = song;
+    let mut player = SONG.player();
+    for _ in 0..song.frames.len() {
+        let (mut expected, mut changes) = (Vec::new(), Vec::new());
+        song.produce_next_ay_frame(|ts, reg, val| expected.push((ts, reg, val)));
+        player.produce_next_ay_frame(|ts, reg, val| changes.push((ts, reg, val)));
+        assert_eq!(changes, expected);
+    }
+    assert_eq!(player.cursor(), 2);
+}
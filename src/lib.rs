@@ -2,13 +2,19 @@
 //!
 //! This [format] was designed by [Leonard/OXYGENE] for his AY-emulator [StSound].
 //!
-//! YM-files are distributed as compressed [LHA] archives.
+//! YM-files are distributed as compressed [LHA] archives. The Atari YM-files packed with
+//! the `ICE!` 2.x packer are recognized as well. All the songs of multi-entry archives can be
+//! enumerated with [archive::YmArchive].
 //!
 //! This library can help uncompress, parse the YM-files, and produce the AY/YM register changes
 //! for the players.
 //!
 //! The following YM-file types are supported: `YM2!`, `YM3!`, `YM3B`, `YM4!`, `YM5!` and `YM6!`.
 //!
+//! The sample based YM-Tracker files: `YMT1` and `YMT2` and the YM digi-mix files: `MIX1` can be
+//! parsed with [StSoundSong::parse_any] and rendered to PCM with [tracker::YmTrackerSong::render]
+//! and [mix::YmMixSong::render].
+//!
 //! All special [effects] described by Leonard are being recognized.
 //! [Here][YmFrame] is the description of how those special effects and AY/YM register data
 //! are being encoded in frames.
@@ -26,7 +32,7 @@ pub use ym::*;
 /// Attempts to parse an YM-file that can be either compressed or uncompressed, from the
 /// given file `path`.
 ///
-/// Returns an instance of `YmSong` on success.
+/// Returns an instance of `YmSong` on success. See [YmSong::parse_any].
 pub fn parse_file<P: AsRef<Path>>(path: P) -> io::Result<YmSong> {
     let file = fs::File::open(path.as_ref())?;
     let file_name = path.as_ref().file_name()
@@ -6,17 +6,46 @@ use chrono::NaiveDateTime;
 
 pub mod flags;
 pub mod effects;
+pub mod tuning;
+pub mod resample;
+pub mod analysis;
+pub mod notes;
+pub mod midi;
+pub mod psg;
+mod vtx;
+#[cfg(feature = "fym")]
+mod fym;
+pub mod vgm;
+pub mod ayc;
+pub mod tracker;
+pub mod mix;
+pub mod pt3;
+pub mod source;
+pub mod embed;
+pub mod archive;
+mod dosound;
+mod bits;
+mod edit;
+mod ice;
+mod lh5;
+mod mym;
 mod parse;
 mod player;
+mod write;
 
 use flags::*;
 use effects::*;
+use tracker::YmTrackerSong;
+use mix::YmMixSong;
+use player::PlayerState;
 
 pub const MAX_DD_SAMPLES: usize = 32;
 
 pub const MFP_TIMER_FREQUENCY: u32 = 2_457_600;
 const DEFAULT_CHIPSET_FREQUENCY: u32 = 2_000_000;
 const DEFAULT_FRAME_FREQUENCY: u16 = 50;
+/// Timer pre-divisors indexed by their encoded value `PPP - 1`.
+const TIMER_PREDIVISORS: [u32;7] = [4, 10, 16, 50, 64, 100, 200];
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum YmVersion {
@@ -40,12 +69,47 @@ impl YmVersion {
     }
 }
 
+/// The type of the sound chip the song was created for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChipType {
+    /// General Instrument AY-3-8910/8912.
+    Ay,
+    /// Yamaha YM2149.
+    #[default]
+    Ym,
+}
+
+/// The placement of the voice channels in the stereo panorama, listed from left to right.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StereoLayout {
+    #[default]
+    Mono,
+    Abc,
+    Acb,
+    Bac,
+    Bca,
+    Cab,
+    Cba,
+}
+
 impl fmt::Display for YmVersion {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.tag().fmt(f)
     }
 }
 
+/// Any of the music files of the StSound family.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum StSoundSong {
+    /// The AY/YM register song: `YM2!`, `YM3!`, `YM3b`, `YM4!`, `YM5!` or `YM6!`.
+    Ym(YmSong),
+    /// The YM-Tracker song: `YMT1` or `YMT2`.
+    Tracker(YmTrackerSong),
+    /// The YM digi-mix song: `MIX1`.
+    Mix(YmMixSong),
+}
+
 /// The **YM** music file.
 ///
 /// The YM-file consist of [YmFrame]s that represent the state of the AY/YM chipset registers and
@@ -66,6 +130,16 @@ pub struct YmSong {
     pub author: String,
     /// The comment.
     pub comments: String,
+    /// The name of the game, demo or other program the song comes from.
+    pub program: String,
+    /// The name of the music editor the song was created with.
+    pub tracker: String,
+    /// The year of the song release, `0` if unknown.
+    pub year: u16,
+    /// The type of the sound chip the song was created for.
+    pub chip_type: ChipType,
+    /// The intended stereo placement of the voice channels.
+    pub stereo: StereoLayout,
     /// The number of cycles per second of the AY/YM chipset clock.
     pub chipset_frequency: u32,
     /// The number of frames played each second.
@@ -78,9 +152,7 @@ pub struct YmSong {
     pub dd_samples: Box<[u8]>,
     /// `DIGI-DRUM` sample end indexes in [YmSong::dd_samples].
     pub dd_samples_ends: [usize;MAX_DD_SAMPLES],
-        cursor: usize,
-        voice_effects: [(SidVoice, SinusSid, DigiDrum); 3],
-        buzzer: SyncBuzzer,
+        player: PlayerState,
 }
 
 /// This type represent the state of the AY/YM chipset registers and contain additional information
@@ -225,15 +297,18 @@ impl YmSong {
             title,
             author: String::new(),
             comments: String::new(),
+            program: String::new(),
+            tracker: String::new(),
+            year: 0,
+            chip_type: ChipType::default(),
+            stereo: StereoLayout::default(),
             chipset_frequency: DEFAULT_CHIPSET_FREQUENCY,
             frame_frequency: DEFAULT_FRAME_FREQUENCY,
             loop_frame,
             frames,
             dd_samples: Box::new([]),
             dd_samples_ends: [0usize;MAX_DD_SAMPLES],
-            cursor: 0,
-            voice_effects: Default::default(),
-            buzzer: Default::default()
+            player: PlayerState::default()
         }
     }
 
@@ -279,13 +354,12 @@ impl YmSong {
 
     /// Returns the number of AY/YM chipset clock cycles of a single music frame.
     pub fn frame_cycles(&self) -> f32 {
-        self.clock_frequency() / self.frame_frequency as f32
+        self.song_data().frame_cycles()
     }
 
     /// Calculates the timer interval in clock cycles, from the given `divisor`.
     pub fn timer_interval(&self, divisor: NonZeroU32) -> f32 {
-        let divisor = divisor.get() as f32;
-        self.clock_frequency() as f32 * divisor / MFP_TIMER_FREQUENCY as f32
+        self.song_data().timer_interval(divisor)
     }
 
     /// Returns the indicated sample data range in the [YmSong::dd_samples] for the given `sample`.
@@ -293,12 +367,7 @@ impl YmSong {
     /// # Panics
     /// Panics if `sample` value is not below [MAX_DD_SAMPLES].
     pub fn sample_data_range(&self, sample: usize) -> Range<usize> {
-        let end = self.dd_samples_ends[sample];
-        let start = match sample {
-            0 => 0,
-            index => self.dd_samples_ends[index - 1]
-        };
-        start..end
+        self.song_data().sample_data_range(sample)
     }
 }
 
@@ -329,6 +398,51 @@ impl YmFrame {
         self.data[(VOL_A_REG + chan) as usize] & 0x1f
     }
 
+    /// Returns the tone period of the indicated `chan` from registers 0 to 5.
+    ///
+    /// The 2 lowest bits of `chan` indicate the voice channel, see [YmFrame::vol].
+    pub fn tone_period(&self, chan: u8) -> u16 {
+        let reg = ((chan & 3) << 1) as usize;
+        debug_assert!(reg < 6);
+        u16::from_le_bytes([self.data[reg], self.data[reg + 1] & 0x0f])
+    }
+
+    /// Sets the tone period of the indicated `chan`, preserving the special effect control bits.
+    ///
+    /// Only the 12 lowest bits of `period` are used.
+    pub fn set_tone_period(&mut self, chan: u8, period: u16) {
+        let reg = ((chan & 3) << 1) as usize;
+        debug_assert!(reg < 6);
+        let [fine, coarse] = period.to_le_bytes();
+        self.data[reg] = fine;
+        self.data[reg + 1] = (self.data[reg + 1] & 0xf0) | (coarse & 0x0f);
+    }
+
+    /// Returns the noise period from the register 6.
+    pub fn noise_period(&self) -> u8 {
+        self.data[NOISE_PER_REG as usize] & 0x1f
+    }
+
+    /// Sets the noise period, preserving the timer pre-divisor bits.
+    ///
+    /// Only the 5 lowest bits of `period` are used.
+    pub fn set_noise_period(&mut self, period: u8) {
+        let reg = &mut self.data[NOISE_PER_REG as usize];
+        *reg = (*reg & 0xe0) | (period & 0x1f);
+    }
+
+    /// Returns the envelope period from registers 11 and 12.
+    pub fn env_period(&self) -> u16 {
+        u16::from_le_bytes([self.data[ENV_PER_FINE_REG as usize], self.data[ENV_PER_COARSE_REG as usize]])
+    }
+
+    /// Sets the envelope period in registers 11 and 12.
+    pub fn set_env_period(&mut self, period: u16) {
+        let [fine, coarse] = period.to_le_bytes();
+        self.data[ENV_PER_FINE_REG as usize] = fine;
+        self.data[ENV_PER_COARSE_REG as usize] = coarse;
+    }
+
     /// Calculates the timer divsor for the special effect `fx0`.
     pub fn timer_divisor0(&self) -> Option<NonZeroU32> {
         calculate_timer_divisor(self.data[6], self.data[14])
@@ -340,6 +454,35 @@ impl YmFrame {
     }
 }
 
+/// Finds the pre-divisor and divisor encoding nearest to the `wanted` timer divisor.
+///
+/// Returns the pre-divisor in the 3 highest bits and the 8-bit divisor.
+fn encode_timer_divisor(wanted: f64) -> (u8, u8) {
+    let (index, div, _) = nearest_timer_divisor(wanted, 0..TIMER_PREDIVISORS.len());
+    (((index + 1) as u8) << 5, div as u8)
+}
+
+/// Finds the pre-divisor and the 8-bit divisor with the product nearest to the `wanted` timer
+/// divisor, trying the pre-divisors indexed by `candidates` in turn. On equal errors the earlier
+/// candidate wins.
+///
+/// Returns the index into [TIMER_PREDIVISORS], the divisor and whether the divisor had to be
+/// clamped to its range.
+fn nearest_timer_divisor<I: IntoIterator<Item=usize>>(wanted: f64, candidates: I) -> (usize, u32, bool) {
+    let mut best = (0, 1, f64::INFINITY, false);
+    for index in candidates {
+        let prediv = TIMER_PREDIVISORS[index];
+        let rounded = (wanted / prediv as f64).round();
+        let div = rounded.clamp(1.0, 255.0) as u32;
+        let error = ((prediv * div) as f64 - wanted).abs();
+        if error < best.2 {
+            best = (index, div, error, rounded != div as f64);
+        }
+    }
+    let (index, div, _, clamped) = best;
+    (index, div, clamped)
+}
+
 fn calculate_timer_divisor(prediv3: u8, div8: u8) -> Option<NonZeroU32> {
     let prediv = match prediv3 & 0b11100000 {
         0b00000000 => 0,
@@ -0,0 +1,251 @@
+//! Song analysis.
+use super::*;
+
+/// The number of clock cycles of a single envelope ramp for each unit of the envelope period.
+const ENV_RAMP_CYCLES: f32 = 256.0;
+
+/// Options for [YmSong::detect_loop].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopOptions {
+    /// The largest difference of any register value for two frames to be considered equal.
+    ///
+    /// `0` requires an exact repetition.
+    pub tolerance: u8,
+    /// The shortest loop period in frames being considered.
+    pub min_period: u32,
+    /// The smallest number of frames that must repeat for a loop to be reported.
+    pub min_repeat: u32,
+}
+
+/// The result of [YmSong::detect_loop].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoopAnalysis {
+    /// The suggested [YmSong::loop_frame].
+    pub loop_frame: u32,
+    /// The number of frames of a single repetition.
+    pub period: u32,
+    /// The suggested number of frames of the song, with the repeated tail removed.
+    pub nframes: u32,
+    /// The number of frames found repeating after the first period.
+    pub repeated: u32,
+    /// The confidence score in the range `[0.0, 1.0]`.
+    ///
+    /// The score is lower if less than a whole period was found repeating or if some frames
+    /// matched only within the tolerance.
+    pub confidence: f32,
+}
+
+impl Default for LoopOptions {
+    fn default() -> Self {
+        LoopOptions { tolerance: 0, min_period: 50, min_repeat: 50 }
+    }
+}
+
+impl YmSong {
+    /// Searches the frames for the earliest start and the shortest period of a repetition lasting
+    /// until the end of the song.
+    ///
+    /// Frames are compared by their decoded state: the bits of the registers that are not in use
+    /// are being ignored, and the effect control data is being compared only for the active
+    /// special effects.
+    ///
+    /// Returns `None` if no repetition satisfying `options` has been found.
+    pub fn detect_loop(&self, options: LoopOptions) -> Option<LoopAnalysis> {
+        let keys: Vec<[u8;16]> = self.frames.iter().map(|frame| self.frame_state_key(frame)).collect();
+        let nframes = keys.len();
+        let tolerance = options.tolerance;
+        let matches = |a: &[u8;16], b: &[u8;16]| {
+            a.iter().zip(b.iter()).all(|(&a, &b)| a.abs_diff(b) <= tolerance)
+        };
+        let min_period = (options.min_period as usize).max(1);
+        let min_repeat = (options.min_repeat as usize).max(1);
+        let mut best: Option<(usize, usize)> = None;
+        for period in min_period..nframes {
+            if nframes - period < min_repeat {
+                break
+            }
+            let mut start = nframes - period;
+            while start > 0 && matches(&keys[start - 1], &keys[start - 1 + period]) {
+                start -= 1;
+            }
+            let repeated = nframes - period - start;
+            if repeated < min_repeat || keys[start..start + period].iter().all(|k| k == &keys[start]) {
+                continue
+            }
+            if best.is_none_or(|(best_start, _)| start < best_start) {
+                best = Some((start, period));
+            }
+            if start == 0 {
+                break
+            }
+        }
+        best.map(|(start, period)| {
+            let repeated = nframes - period - start;
+            let exact = (start..start + repeated).filter(|&i| keys[i] == keys[i + period]).count();
+            let coverage = (repeated as f32 / period as f32).min(1.0);
+            let confidence = coverage * exact as f32 / repeated as f32;
+            LoopAnalysis {
+                loop_frame: start as u32,
+                period: period as u32,
+                nframes: (start + period) as u32,
+                repeated: repeated as u32,
+                confidence
+            }
+        })
+    }
+
+    /// Decides for each frame whether it produces any audible sound.
+    ///
+    /// The frames are being played by the song's player from the beginning, so the effects
+    /// started in earlier frames, like a `DIGI-DRUM` sample being played, are taken into account.
+    ///
+    /// A voice channel is considered audible in a frame if:
+    ///
+    /// * its volume is not `0` and either the tone or noise is enabled in the mixer for that
+    ///   channel,
+    /// * or its volume is controlled by the envelope, which modulates the channel's level even
+    ///   if both tone and noise are disabled, unless the envelope shape is one of those which
+    ///   hold the level at `0` (`0`-`7`, `9` and `15`) and its single ramp has finished before
+    ///   the frame,
+    /// * or its volume is being changed during the frame by an active `DIGI-DRUM`, `SID voice` or
+    ///   `Sinus SID` effect.
+    pub fn audible_frames(&self) -> Vec<bool> {
+        let mut song = self.clone();
+        song.reset();
+        let frame_cycles = self.frame_cycles();
+        let mut regs = [0u8;14];
+        // the last envelope shape and the number of cycles since it was written
+        let mut envelope: Option<(u8, f32)> = None;
+        (0..self.frames.len()).map(|_| {
+            let mut modulated = [false;3];
+            let mut levels = [None;3];
+            let mut trigger = None;
+            song.produce_next_ay_frame(|ts, reg, val| {
+                if let Some(chan) = reg.checked_sub(VOL_A_REG).filter(|&chan| chan < 3) {
+                    let chan = chan as usize;
+                    match levels[chan] {
+                        Some(level) if level != val => modulated[chan] = true,
+                        _ => levels[chan] = Some(val)
+                    }
+                }
+                if reg == ENV_REG {
+                    trigger = Some((val & 0x0f, ts));
+                }
+                regs[reg as usize] = val;
+            });
+            let env_period = u16::from_le_bytes([regs[ENV_PER_FINE_REG as usize],
+                                                 regs[ENV_PER_COARSE_REG as usize]]).max(1);
+            let env_silent = trigger.is_none() && envelope.is_some_and(|(shape, elapsed)| {
+                is_one_shot_to_zero(shape) && elapsed >= ENV_RAMP_CYCLES * env_period as f32
+            });
+            envelope = match trigger {
+                Some((shape, ts)) => Some((shape, frame_cycles - ts)),
+                None => envelope.map(|(shape, elapsed)| (shape, elapsed + frame_cycles))
+            };
+            let mixer = regs[MIXER_REG as usize];
+            (0..3).any(|chan| {
+                let vol = regs[(VOL_A_REG + chan) as usize] & 0x1f;
+                let tone_or_noise = mixer & (0b001001 << chan) != (0b001001 << chan);
+                modulated[chan as usize] || match vol & 0x10 {
+                    0 => vol != 0 && tone_or_noise,
+                    _ => !env_silent
+                }
+            })
+        }).collect()
+    }
+
+    /// Returns the frame data with the unused bits and the data of inactive effects cleared.
+    pub(super) fn frame_state_key(&self, frame: &YmFrame) -> [u8;16] {
+        let mut key = frame.data;
+        key[1] &= 0x0f;
+        key[3] &= 0x0f;
+        key[5] &= 0x0f;
+        key[NOISE_PER_REG as usize] &= 0x1f;
+        key[MIXER_REG as usize] &= 0x3f;
+        key[VOL_A_REG as usize] &= 0x1f;
+        key[VOL_B_REG as usize] &= 0x1f;
+        key[14] = 0;
+        key[15] = 0;
+        let (fx0, fx1) = match self.version {
+            YmVersion::Ym2 => {
+                key[VOL_C_REG as usize] = frame.data[VOL_C_REG as usize];
+                if frame.data[ENV_REG as usize] == 0xff {
+                    key[ENV_PER_FINE_REG as usize] = 0;
+                }
+                if frame.data[VOL_C_REG as usize] & 0x80 == 0 {
+                    key[ENV_PER_COARSE_REG as usize] = 0;
+                }
+                return key
+            }
+            YmVersion::Ym3 => (false, false),
+            YmVersion::Ym4|YmVersion::Ym5 => {
+                (frame.fx0().ts_channel().is_some(), frame.fx1().dd_channel().is_some())
+            }
+            YmVersion::Ym6 => {
+                (frame.fx0().fx6_channel().is_some(), frame.fx1().fx6_channel().is_some())
+            }
+        };
+        key[VOL_C_REG as usize] &= 0x1f;
+        if fx0 {
+            key[1] = frame.data[1];
+            key[NOISE_PER_REG as usize] = frame.data[NOISE_PER_REG as usize];
+            key[14] = frame.data[14];
+        }
+        if fx1 {
+            key[3] = frame.data[3];
+            key[VOL_A_REG as usize] = (key[VOL_A_REG as usize] & 0x1f) | (frame.data[VOL_A_REG as usize] & 0xe0);
+            key[15] = frame.data[15];
+        }
+        key
+    }
+}
+
+/// Returns `true` if the envelope `shape` holds the level at `0` after a single ramp.
+fn is_one_shot_to_zero(shape: u8) -> bool {
+    matches!(shape, 0..=7|9|15)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_loop_works() {
+        let intro = [1u8, 2, 3, 4, 5];
+        let body = [10u8, 11, 12, 13, 14, 15, 16, 17];
+        let tail = &body[..6];
+        let frames = intro.iter().chain(body.iter()).chain(tail.iter()).map(|&v| {
+            let mut frame = YmFrame::default();
+            frame.data[0] = v;
+            frame.data[ENV_REG as usize] = 0xff;
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 0, String::new(), None);
+        let options = LoopOptions { tolerance: 0, min_period: 2, min_repeat: 4 };
+        let found = song.detect_loop(options).unwrap();
+        assert_eq!(found.loop_frame, 5);
+        assert_eq!(found.period, 8);
+        assert_eq!(found.nframes, 13);
+        assert_eq!(found.repeated, 6);
+        assert_eq!(found.confidence, 0.75);
+        assert_eq!(song.detect_loop(LoopOptions { min_repeat: 7, ..options }), None);
+    }
+
+    #[test]
+    fn audible_frames_ends_one_shot_envelopes() {
+        let song_with_shape = |shape: u8| {
+            let frames = (0..4).map(|n| {
+                let mut frame = YmFrame::default();
+                frame.data[MIXER_REG as usize] = 0b111110;
+                frame.data[VOL_A_REG as usize] = 0x10;
+                frame.set_env_period(10);
+                frame.data[ENV_REG as usize] = if n == 0 { shape } else { 0xff };
+                frame
+            }).collect();
+            YmSong::new(YmVersion::Ym5, frames, 0, String::new(), None)
+        };
+        assert_eq!(song_with_shape(0x09).audible_frames(), [true, false, false, false]);
+        assert_eq!(song_with_shape(0x0d).audible_frames(), [true, true, true, true]);
+        assert_eq!(song_with_shape(0x0e).audible_frames(), [true, true, true, true]);
+    }
+}
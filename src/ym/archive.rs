@@ -0,0 +1,148 @@
+//! Multi-entry LHA archives.
+//!
+//! YM-file collections are often distributed as a single LHA archive with many songs and some
+//! text files. [YmArchive] enumerates all the archive entries and parses the YM-files on demand.
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use delharc::LhaDecodeReader;
+
+pub use delharc::{CompressionMethod, OsType};
+
+use super::*;
+use super::parse::{is_ym_ident, parse_ym_ident};
+
+/// The LHA archive reader enumerating the archive entries.
+///
+/// The archive is being read sequentially, so each entry can be parsed only before advancing
+/// to the next one with [YmArchive::next_entry].
+pub struct YmArchive<R> {
+        lha: LhaDecodeReader<R>,
+        started: bool,
+        finished: bool,
+}
+
+/// The archive entry with its header information.
+pub struct YmArchiveEntry<'a, R> {
+    /// The path of the entry in the archive.
+    pub path: PathBuf,
+    /// The last modification timestamp of the entry, if present.
+    pub modified: Option<NaiveDateTime>,
+    /// The operating system the entry was archived on, if recognized.
+    pub os_type: Option<OsType>,
+    /// The compression method of the entry, if recognized.
+    pub compression: Option<CompressionMethod>,
+    /// The uncompressed size of the entry in bytes.
+    pub len: u64,
+        lha: &'a mut LhaDecodeReader<R>,
+}
+
+impl<R: Read> YmArchive<R> {
+    /// Opens the LHA archive from the given stream source and reads the header of the first entry.
+    pub fn open(rd: R) -> io::Result<Self> {
+        let lha = LhaDecodeReader::new(rd)?;
+        Ok(YmArchive { lha, started: false, finished: false })
+    }
+
+    /// Returns the next archive entry or `None` if there are no more entries.
+    ///
+    /// The unread data of the previous entry is skipped.
+    pub fn next_entry(&mut self) -> io::Result<Option<YmArchiveEntry<'_, R>>> {
+        if self.finished {
+            return Ok(None)
+        }
+        if self.started && !self.lha.next_file()? {
+            self.finished = true;
+            return Ok(None)
+        }
+        self.started = true;
+        let header = self.lha.header();
+        Ok(Some(YmArchiveEntry {
+            path: header.parse_pathname(),
+            modified: header.parse_last_modified().to_naive_utc(),
+            os_type: header.parse_os_type().ok(),
+            compression: header.compression_method().ok(),
+            len: self.lha.len(),
+            lha: &mut self.lha
+        }))
+    }
+}
+
+impl<R: Read> YmArchiveEntry<'_, R> {
+    /// Returns `true` if the entry compression method is supported.
+    pub fn is_supported(&self) -> bool {
+        self.lha.is_decoder_supported()
+    }
+
+    /// Returns the file name part of the entry path.
+    pub fn file_name(&self) -> String {
+        self.path.file_name()
+                 .map(|s| s.to_string_lossy().into_owned())
+                 .unwrap_or_default()
+    }
+
+    /// Attempts to parse the entry as a YM-file.
+    ///
+    /// The file name of the entry is used as a fallback song title.
+    ///
+    /// Returns `Ok(None)` if the entry is not a YM-file, e.g. a text file. Returns an error if
+    /// the entry compression method is not supported or the YM-file is malformed.
+    pub fn parse(self) -> io::Result<Option<YmSong>> {
+        if !self.is_supported() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported compression method"))
+        }
+        if self.len < 4 {
+            return Ok(None)
+        }
+        let title = self.file_name();
+        let mut rd = io::BufReader::new(self.lha);
+        let mut ident = [0u8;4];
+        rd.read_exact(&mut ident)?;
+        if !is_ym_ident(&ident) {
+            return Ok(None)
+        }
+        parse_ym_ident(ident, &mut rd, self.len, title, self.modified).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lh0_entry(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut header = vec![0, 0];
+        header.extend_from_slice(b"-lh0-");
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&[0x00, 0x60, 0x21, 0x28, 0x20, 0x00, name.len() as u8]);
+        header.extend_from_slice(name.as_bytes());
+        header.extend_from_slice(&[0, 0]);
+        header[0] = (header.len() - 2) as u8;
+        header[1] = header[2..].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        header.extend_from_slice(data);
+        header
+    }
+
+    #[test]
+    fn archive_works() {
+        let mut ym = b"YM3!".to_vec();
+        ym.extend((0..28).map(|n| n as u8));
+        let mut lzh = lh0_entry("readme.txt", b"Hello!");
+        lzh.extend(lh0_entry("music/song.ym", &ym));
+        lzh.push(0);
+        let mut archive = YmArchive::open(&lzh[..]).unwrap();
+        let entry = archive.next_entry().unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("readme.txt"));
+        assert_eq!(entry.compression, Some(CompressionMethod::Lh0));
+        assert_eq!(entry.len, 6);
+        assert!(entry.parse().unwrap().is_none());
+        let entry = archive.next_entry().unwrap().unwrap();
+        assert_eq!(entry.file_name(), "song.ym");
+        let song = entry.parse().unwrap().unwrap();
+        assert_eq!(song.version, YmVersion::Ym3);
+        assert_eq!(song.title, "song.ym");
+        assert_eq!(song.frames.len(), 2);
+        assert!(archive.next_entry().unwrap().is_none());
+        assert!(archive.next_entry().unwrap().is_none());
+    }
+}
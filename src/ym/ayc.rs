@@ -0,0 +1,247 @@
+//! AYC (Amstrad CPC compressed register dump) file conversion.
+//!
+//! The file starts with the number of frames as a 16-bit little-endian integer, followed by
+//! 14 register entries of 3 bytes: the high byte of the decoding buffer size (`1` for a 256-byte,
+//! `4` for a 1024-byte buffer) and the 16-bit little-endian offset of the register's data stream
+//! from the beginning of the file.
+//!
+//! Each register's stream holds the register value for every frame, packed in groups of 8 items
+//! preceded by a flag byte. The flag bits, starting from the most significant, select for each
+//! item either a literal byte (`0`) or a reference (`1`): the number of bytes to copy followed by
+//! the distance back to copy from, as a single byte for 256-byte buffers, where `0` means 256,
+//! or as a 16-bit little-endian integer for 1024-byte buffers. The references never reach
+//! further back than the buffer size, so the player can unpack each register into its own
+//! circular buffer.
+//!
+//! The envelope shape register value `0xff` means no write, like in [YmFrame].
+use std::io::{self, Read, Write};
+
+use super::*;
+use super::psg::clear_unused_bits;
+
+/// The AY-3-8912 clock frequency of the Amstrad CPC.
+pub const AYC_CHIPSET_FREQUENCY: u32 = 1_000_000;
+/// The frame frequency of `AYC` files.
+pub const AYC_FRAME_FREQUENCY: u16 = 50;
+
+const AYC_REGS: usize = 14;
+const AYC_HEADER_SIZE: usize = 2 + AYC_REGS * 3;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 255;
+
+/// The size of the buffer each register stream is unpacked into by the player.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AycWindow {
+    /// 256 bytes per register.
+    #[default]
+    Small,
+    /// 1024 bytes per register, packs better but requires more memory.
+    Large,
+}
+
+impl AycWindow {
+    /// Returns the buffer size in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            AycWindow::Small => 256,
+            AycWindow::Large => 1024
+        }
+    }
+
+    fn from_high_byte(byte: u8) -> Option<AycWindow> {
+        match byte {
+            1 => Some(AycWindow::Small),
+            4 => Some(AycWindow::Large),
+            _ => None
+        }
+    }
+}
+
+impl YmSong {
+    /// Attempts to parse an `AYC` file from the given stream source.
+    ///
+    /// Provide `file_name` which will be used as the song title.
+    ///
+    /// The new song is a `YM6!` song without special effects, with the Amstrad CPC
+    /// [AYC_CHIPSET_FREQUENCY] and a [AYC_FRAME_FREQUENCY] frame rate.
+    pub fn parse_ayc<R, S>(mut rd: R, file_name: S) -> io::Result<YmSong>
+        where R: Read, S: Into<String>
+    {
+        let mut data = Vec::new();
+        rd.read_to_end(&mut data)?;
+        if data.len() < AYC_HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely"))
+        }
+        let nframes = u16::from_le_bytes([data[0], data[1]]) as usize;
+        if nframes == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no frames"))
+        }
+        let mut frames = vec![YmFrame::default();nframes];
+        for (reg, entry) in data[2..AYC_HEADER_SIZE].chunks_exact(3).enumerate() {
+            let window = AycWindow::from_high_byte(entry[0]).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid AYC buffer size")
+            })?;
+            let offset = u16::from_le_bytes([entry[1], entry[2]]) as usize;
+            let stream = data.get(offset..).filter(|_| offset >= AYC_HEADER_SIZE).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "invalid AYC register offset")
+            })?;
+            let values = unpack_register(stream, nframes, window)?;
+            for (frame, val) in frames.iter_mut().zip(values) {
+                frame.data[reg] = val;
+            }
+        }
+        for frame in frames.iter_mut() {
+            clear_unused_bits(&mut frame.data);
+        }
+        Ok(YmSong::new(YmVersion::Ym6, frames.into_boxed_slice(), 0, file_name.into(), None)
+                  .with_frequency(AYC_CHIPSET_FREQUENCY, AYC_FRAME_FREQUENCY))
+    }
+
+    /// Writes the song as an `AYC` file to the given stream, with the decoding buffers of the
+    /// given `window` size.
+    ///
+    /// The song is retuned to the [AYC_CHIPSET_FREQUENCY] and resampled to the
+    /// [AYC_FRAME_FREQUENCY] if necessary. The YM-specific special effects are dropped, the same
+    /// way as in [YmSong::write_psg].
+    ///
+    /// Returns an error if the song has more than `65535` frames or the packed data doesn't fit
+    /// in 64 KiB.
+    pub fn write_ayc<W: Write>(&self, mut wr: W, window: AycWindow) -> io::Result<()> {
+        let frames = self.retimed(AYC_CHIPSET_FREQUENCY, AYC_FRAME_FREQUENCY).ay_register_dump();
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "song too large for AYC");
+        let nframes = u16::try_from(frames.len()).map_err(|_| too_large())?;
+
+        let mut dump: Vec<Vec<u8>> = (0..AYC_REGS).map(|_| Vec::with_capacity(frames.len())).collect();
+        for regs in frames {
+            for (values, val) in dump.iter_mut().zip(regs) {
+                values.push(val);
+            }
+        }
+
+        let mut header = Vec::with_capacity(AYC_HEADER_SIZE);
+        header.extend_from_slice(&nframes.to_le_bytes());
+        let mut streams = Vec::new();
+        for values in dump.iter() {
+            let offset = u16::try_from(AYC_HEADER_SIZE + streams.len()).map_err(|_| too_large())?;
+            header.push((window.size() >> 8) as u8);
+            header.extend_from_slice(&offset.to_le_bytes());
+            streams.extend_from_slice(&pack_register(values, window));
+        }
+        wr.write_all(&header)?;
+        wr.write_all(&streams)
+    }
+}
+
+fn unpack_register(mut stream: &[u8], nframes: usize, window: AycWindow) -> io::Result<Vec<u8>> {
+    let truncated = || io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely");
+    let mut next = move || -> io::Result<u8> {
+        let (&byte, rest) = stream.split_first().ok_or_else(truncated)?;
+        stream = rest;
+        Ok(byte)
+    };
+    let mut out = Vec::with_capacity(nframes);
+    while out.len() < nframes {
+        let flags = next()?;
+        for bit in (0..8).rev() {
+            if out.len() >= nframes {
+                break
+            }
+            if flags & (1 << bit) == 0 {
+                out.push(next()?);
+                continue
+            }
+            let len = next()? as usize;
+            let distance = match window {
+                AycWindow::Small => match next()? {
+                    0 => 256,
+                    distance => distance as usize
+                },
+                AycWindow::Large => u16::from_le_bytes([next()?, next()?]) as usize
+            };
+            if len == 0 || distance == 0 || distance > window.size() || distance > out.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid AYC reference"))
+            }
+            let start = out.len() - distance;
+            for index in start..start + len.min(nframes - out.len()) {
+                let byte = out[index];
+                out.push(byte);
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn pack_register(values: &[u8], window: AycWindow) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut flags_pos = 0;
+    let mut item = 0;
+    let mut pos = 0;
+    while pos < values.len() {
+        if item % 8 == 0 {
+            flags_pos = out.len();
+            out.push(0);
+        }
+        let max_len = MAX_MATCH.min(values.len() - pos);
+        let mut best = (0, 0);
+        for distance in 1..=window.size().min(pos) {
+            let start = pos - distance;
+            let len = (0..max_len).take_while(|&i| values[start + i] == values[pos + i]).count();
+            if len > best.0 {
+                best = (len, distance);
+                if len == max_len {
+                    break
+                }
+            }
+        }
+        if best.0 >= MIN_MATCH {
+            out[flags_pos] |= 0x80 >> (item % 8);
+            out.push(best.0 as u8);
+            match window {
+                AycWindow::Small => out.push(best.1 as u8),
+                AycWindow::Large => out.extend_from_slice(&(best.1 as u16).to_le_bytes())
+            }
+            pos += best.0;
+        }
+        else {
+            out.push(values[pos]);
+            pos += 1;
+        }
+        item += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ayc_roundtrip_works() {
+        let frames: Box<[YmFrame]> = (0..1000u32).map(|n| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(0, [200, 300, 400, 250][(n / 4 % 4) as usize]);
+            frame.data[VOL_A_REG as usize] = (15 - n % 16) as u8;
+            frame.data[ENV_REG as usize] = if n % 300 == 0 { 0x0a } else { 0xff };
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 0, String::new(), None)
+                         .with_frequency(AYC_CHIPSET_FREQUENCY, 50);
+        for window in [AycWindow::Small, AycWindow::Large] {
+            let mut ayc = Vec::new();
+            song.write_ayc(&mut ayc, window).unwrap();
+            assert_eq!(ayc[2], (window.size() >> 8) as u8);
+            assert!(ayc.len() < 1000);
+            let parsed = YmSong::parse_ayc(&ayc[..], "tune").unwrap();
+            assert_eq!(parsed.chipset_frequency, AYC_CHIPSET_FREQUENCY);
+            assert_eq!(parsed.frames.len(), 1000);
+            for (a, b) in parsed.frames.iter().zip(song.frames.iter()) {
+                assert_eq!(a.data, b.data);
+            }
+        }
+        // retuned from the Atari ST clock
+        let mut ayc = Vec::new();
+        song.clone().with_frequency(2_000_000, 50).write_ayc(&mut ayc, AycWindow::Small).unwrap();
+        let parsed = YmSong::parse_ayc(&ayc[..], "tune").unwrap();
+        assert_eq!(parsed.frames[0].tone_period(0), 100);
+    }
+}
@@ -0,0 +1,74 @@
+//! Bit streams, most significant bits first.
+
+/// Reads bits starting from the most significant bit, zeros are read past the end of the data.
+pub(super) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bitbuf: u64,
+    bitcount: u32,
+}
+
+impl<'a> BitReader<'a> {
+    pub(super) fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0, bitbuf: 0, bitcount: 0 }
+    }
+
+    /// Returns `true` if more bits were read than available in the data.
+    pub(super) fn is_overrun(&self) -> bool {
+        self.pos * 8 - self.bitcount as usize > self.data.len() * 8
+    }
+
+    fn fill(&mut self) {
+        while self.bitcount <= 56 {
+            let byte = self.data.get(self.pos).copied().unwrap_or(0);
+            self.pos += 1;
+            self.bitbuf |= (byte as u64) << (56 - self.bitcount);
+            self.bitcount += 8;
+        }
+    }
+
+    pub(super) fn bits(&mut self, n: u32) -> u32 {
+        if n == 0 {
+            return 0
+        }
+        self.fill();
+        let value = (self.bitbuf >> (64 - n)) as u32;
+        self.bitbuf <<= n;
+        self.bitcount -= n;
+        value
+    }
+
+    pub(super) fn bit(&mut self) -> bool {
+        self.bits(1) != 0
+    }
+}
+
+/// Writes bits starting from the most significant bit.
+#[derive(Default)]
+pub(super) struct BitWriter {
+    out: Vec<u8>,
+    bitbuf: u32,
+    bitcount: u32,
+}
+
+impl BitWriter {
+    pub(super) fn put(&mut self, n: u32, value: u32) {
+        for shift in (0..n).rev() {
+            self.bitbuf = (self.bitbuf << 1) | ((value >> shift) & 1);
+            self.bitcount += 1;
+            if self.bitcount == 8 {
+                self.out.push(self.bitbuf as u8);
+                self.bitbuf = 0;
+                self.bitcount = 0;
+            }
+        }
+    }
+
+    /// Returns the written data, the last byte is padded with zeros.
+    pub(super) fn finish(mut self) -> Vec<u8> {
+        if self.bitcount != 0 {
+            self.out.push((self.bitbuf << (8 - self.bitcount)) as u8);
+        }
+        self.out
+    }
+}
@@ -0,0 +1,154 @@
+//! Atari ST XBIOS `Dosound` command list export.
+//!
+//! The command list is being interpreted by the XBIOS on each 50 Hz timer tick:
+//!
+//! * `0x00`-`0x0F` `value`: writes the value to the register,
+//! * `0x80` `value`: loads the temporary register,
+//! * `0x81` `register` `increment` `end`: writes the temporary register to the register on each
+//!   tick, adding the signed increment to it, until it reaches the end value,
+//! * `0x82`-`0xFF` `ticks`: waits the number of ticks, or stops the sound if `ticks` is `0`.
+use super::*;
+
+/// The minimum number of frames of a single register ramp to be encoded with a sweep command.
+const MIN_SWEEP_FRAMES: usize = 4;
+/// The mixer register I/O port bits, the ST uses both ports as outputs.
+const MIXER_IO_OUTPUTS: u8 = 0xc0;
+
+impl YmSong {
+    /// Creates an XBIOS `Dosound` command list from the song.
+    ///
+    /// The song is retuned to the Atari ST chipset clock and resampled to 50 Hz if necessary.
+    /// Only the changed registers are written on each tick. Single register ramps, like volume
+    /// fades, are encoded with the sweep commands. The command list ends with muting all the
+    /// voice channels, followed by a stop command, and can be included in an Atari program as is.
+    ///
+    /// The `Dosound` interpreter can't play the MFP timer effects and `DIGI-DRUM` samples, so
+    /// they are dropped with a warning, the same way as in [YmSong::write_psg]. The song loop
+    /// is ignored, as the command list is played only once.
+    pub fn to_dosound(&self) -> Vec<u8> {
+        let mut dump = self.retimed(DEFAULT_CHIPSET_FREQUENCY, DEFAULT_FRAME_FREQUENCY).ay_register_dump();
+        for regs in dump.iter_mut() {
+            regs[MIXER_REG as usize] |= MIXER_IO_OUTPUTS;
+        }
+
+        let mut out = Vec::new();
+        let mut last: [Option<u8>;14] = [None;14];
+        let mut wait = 0usize;
+        let mut index = 0;
+        while index < dump.len() {
+            let regs = &dump[index];
+            let changes: Vec<u8> = (0..14).filter(|&reg| is_change(reg, regs, &last)).collect();
+            let sweep = find_sweep(&dump, index);
+            if !changes.is_empty() || sweep.is_some() {
+                push_wait(&mut out, wait);
+                wait = 0;
+            }
+            for &reg in changes.iter() {
+                if sweep.is_none_or(|(sweep_reg, ..)| sweep_reg != reg) {
+                    out.extend_from_slice(&[reg, regs[reg as usize]]);
+                    last[reg as usize] = Some(regs[reg as usize]);
+                }
+            }
+            match sweep {
+                Some((reg, step, len)) => {
+                    let start = regs[reg as usize];
+                    let end = start.wrapping_add((step as u8).wrapping_mul(len as u8));
+                    out.extend_from_slice(&[0x80, start, 0x81, reg, step as u8, end]);
+                    last[reg as usize] = Some(dump[index + len - 1][reg as usize]);
+                    index += len;
+                }
+                None => {
+                    wait += 1;
+                    index += 1;
+                }
+            }
+        }
+        push_wait(&mut out, wait);
+        // the stop command leaves the registers as they are
+        for reg in [VOL_A_REG, VOL_B_REG, VOL_C_REG] {
+            out.extend_from_slice(&[reg, 0]);
+        }
+        out.extend_from_slice(&[0xff, 0]);
+        out
+    }
+}
+
+fn is_change(reg: u8, regs: &[u8;14], last: &[Option<u8>;14]) -> bool {
+    let val = regs[reg as usize];
+    match reg {
+        ENV_REG => val != 0xff,
+        _ => last[reg as usize] != Some(val)
+    }
+}
+
+fn push_wait(out: &mut Vec<u8>, mut ticks: usize) {
+    while ticks != 0 {
+        let count = ticks.min(255);
+        out.extend_from_slice(&[0xff, count as u8]);
+        ticks -= count;
+    }
+}
+
+/// Returns the register, the step and the number of frames of the ramp starting at `index`,
+/// where no other register changes.
+fn find_sweep(dump: &[[u8;14]], index: usize) -> Option<(u8, i8, usize)> {
+    let next = dump.get(index + 1)?;
+    let regs = &dump[index];
+    let reg = single_change(regs, next)?;
+    let step = next[reg as usize].wrapping_sub(regs[reg as usize]) as i8;
+    let mut len = 2;
+    while let Some(next) = dump.get(index + len) {
+        let prev = &dump[index + len - 1];
+        if single_change(prev, next) != Some(reg) ||
+           next[reg as usize].wrapping_sub(prev[reg as usize]) as i8 != step {
+            break
+        }
+        len += 1;
+    }
+    // the sweep ends when the temporary register reaches the end value
+    if let Some(period) = (1..len).find(|&n| (step as u8).wrapping_mul(n as u8) == 0) {
+        len = period;
+    }
+    (len >= MIN_SWEEP_FRAMES).then_some((reg, step, len))
+}
+
+/// Returns the only register changed between `prev` and `next` frames.
+fn single_change(prev: &[u8;14], next: &[u8;14]) -> Option<u8> {
+    if next[ENV_REG as usize] != 0xff {
+        return None
+    }
+    let mut changed = (0..ENV_REG).filter(|&reg| prev[reg as usize] != next[reg as usize]);
+    match (changed.next(), changed.next()) {
+        (Some(reg), None) => Some(reg),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_dosound_works() {
+        let frames = (0..20u32).map(|n| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(0, 0x123);
+            frame.data[MIXER_REG as usize] = 0b111110;
+            frame.data[VOL_A_REG as usize] = 15u32.saturating_sub(n.saturating_sub(2)) as u8;
+            frame.data[ENV_REG as usize] = 0xff;
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 0, String::new(), None);
+        let dosound = song.to_dosound();
+        assert_eq!(&dosound[..4], &[0, 0x23, 1, 0x01]);
+        assert_eq!(&dosound[14..24], &[MIXER_REG, 0xfe, VOL_A_REG, 15, 9, 0, 10, 0, 11, 0]);
+        assert_eq!(&dosound[26..], &[
+            0xff, 2, // frames 0-1
+            0x80, 15, 0x81, VOL_A_REG, 0xff, 0xff, // frames 2-17
+            0xff, 2, // frames 18-19
+            VOL_A_REG, 0, VOL_B_REG, 0, VOL_C_REG, 0,
+            0xff, 0
+        ]);
+        assert!(dosound.ends_with(&[0xff, 0]));
+    }
+}
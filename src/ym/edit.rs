@@ -0,0 +1,419 @@
+use core::ops::{Bound, Range, RangeBounds};
+use std::io;
+
+use log::warn;
+
+use super::*;
+use super::resample::ResampleMode;
+use super::analysis::LoopOptions;
+
+impl YmSong {
+    /// Creates a new song from the indicated `range` of frames of this song.
+    ///
+    /// The first frame of the new song includes the last envelope shape that was set before the
+    /// cut point, so the envelope generator starts in the same state. A `DIGI-DRUM` sample
+    /// that is still being played at the cut point is not resumed.
+    ///
+    /// The `loop_frame` is being preserved if it's within the `range`, otherwise the new song loops
+    /// from the beginning.
+    ///
+    /// # Panics
+    /// Panics if the `range` is empty or out of bounds.
+    pub fn slice<R: RangeBounds<usize>>(&self, range: R) -> YmSong {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start + 1,
+            Bound::Unbounded => 0
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end + 1,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.frames.len()
+        };
+        assert!(start < end && end <= self.frames.len(), "invalid frame range");
+        let mut frames: Box<[YmFrame]> = self.frames[start..end].into();
+        let first = &mut frames[0];
+        if first.data[ENV_REG as usize] == 0xff {
+            if let Some(last_shape) = self.frames[..start].iter().rev()
+                                          .map(|f| f.data[ENV_REG as usize])
+                                          .find(|&shape| shape != 0xff)
+            {
+                first.data[ENV_REG as usize] = last_shape;
+            }
+        }
+        let loop_frame = (self.loop_frame as usize)
+                         .checked_sub(start)
+                         .filter(|&index| index < frames.len())
+                         .unwrap_or(0) as u32;
+        YmSong::new(self.version, frames, loop_frame, self.title.clone(), self.created)
+               .with_meta(self.author.clone(), self.comments.clone())
+               .with_samples(self.song_attrs, self.dd_samples.clone(), self.dd_samples_ends)
+               .with_frequency(self.chipset_frequency, self.frame_frequency)
+    }
+
+    /// Removes silent frames from the beginning and the end of the song.
+    ///
+    /// See [YmSong::audible_frames] for the definition of a silent frame. The song is being cut
+    /// with [YmSong::slice], which keeps the envelope state consistent at the new start and
+    /// adjusts the `loop_frame`.
+    ///
+    /// Returns the range of the original frames that were kept. If the whole song is silent
+    /// nothing is being removed.
+    pub fn trim_silence(&mut self) -> Range<usize> {
+        let audible = self.audible_frames();
+        let range = match (audible.iter().position(|&a| a), audible.iter().rposition(|&a| a)) {
+            (Some(start), Some(end)) => start..end + 1,
+            _ => return 0..self.frames.len()
+        };
+        if range.len() != self.frames.len() {
+            *self = self.slice(range.clone());
+        }
+        range
+    }
+
+    /// Splits a song that consists of several tunes into separate songs.
+    ///
+    /// The tune boundaries are found at runs of at least `min_gap` silent frames and at silent
+    /// frames where the registers were reset, i.e. all the periods are `0`. Silent frames
+    /// between the tunes are being dropped. If a repetition is detected within a tune with
+    /// [YmSong::detect_loop], the tune's `loop_frame` is being set and its repeated tail removed.
+    ///
+    /// Each new song shares the metadata of this song, has only the `DIGI-DRUM` samples it uses
+    /// and its title is suffixed with the tune number, starting from 1.
+    pub fn split_subsongs(&self, min_gap: u32) -> Vec<YmSong> {
+        let min_gap = (min_gap as usize).max(1);
+        let mut ranges = Vec::new();
+        let mut tune: Option<Range<usize>> = None;
+        for (index, (audible, frame)) in self.audible_frames().into_iter()
+                                              .zip(self.frames.iter()).enumerate()
+        {
+            if audible {
+                match tune.as_mut() {
+                    Some(range) => range.end = index + 1,
+                    None => tune = Some(index..index + 1)
+                }
+            }
+            else if let Some(range) = tune.as_ref() {
+                if index - range.end + 1 >= min_gap || self.is_reset_frame(frame) {
+                    ranges.extend(tune.take());
+                }
+            }
+        }
+        ranges.extend(tune);
+        ranges.into_iter().enumerate().map(|(index, range)| {
+            let mut song = self.slice(range);
+            if let Some(found) = song.detect_loop(LoopOptions::default())
+                                     .filter(|found| found.confidence >= 0.5)
+            {
+                song = song.slice(..found.nframes as usize);
+                song.loop_frame = found.loop_frame;
+            }
+            song.retain_used_dd_samples();
+            song.title = format!("{} #{}", self.title, index + 1);
+            song
+        }).collect()
+    }
+
+    /// Creates a new song by joining all of the given `songs` one after another.
+    ///
+    /// The first song determines the chipset and frame frequency of the new song, the other songs
+    /// are being retuned and resampled if needed, see [YmSong::retune_to_clock] and [YmSong::resample].
+    ///
+    /// If the songs' versions differ, the frames are converted to `YM6!`. `YM2!` songs can only
+    /// be joined with other `YM2!` songs.
+    ///
+    /// The `DIGI-DRUM` samples are being merged into a single bank, where identical samples are
+    /// stored only once, and the sample numbers in the frames are renumbered accordingly.
+    ///
+    /// The new song loops at the loop frame of the last song, so after the last song ends it
+    /// repeats its own looped part, the same way it would when played alone.
+    ///
+    /// Returns an error if `songs` is empty, the versions can't be reconciled or the merged
+    /// samples exceed [MAX_DD_SAMPLES].
+    pub fn concat(songs: &[YmSong]) -> io::Result<YmSong> {
+        let first = songs.first().ok_or_else(||
+            io::Error::new(io::ErrorKind::InvalidInput, "no songs to concatenate")
+        )?;
+        let version = if songs.iter().all(|song| song.version == first.version) {
+            first.version
+        }
+        else if songs.iter().any(|song| song.version == YmVersion::Ym2) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "YM2! songs can only be concatenated with each other"))
+        }
+        else {
+            YmVersion::Ym6
+        };
+
+        let mut frames = Vec::with_capacity(songs.iter().map(|song| song.frames.len()).sum());
+        let mut dd_samples: Vec<u8> = Vec::new();
+        let mut dd_samples_ends = [0usize;MAX_DD_SAMPLES];
+        let mut nsamples: usize = 0;
+        let mut loop_frame = 0;
+        for song in songs.iter() {
+            let mut song = song.clone();
+            if song.chipset_frequency != first.chipset_frequency {
+                let clamped = song.retune_to_clock(first.chipset_frequency).clamped().count();
+                if clamped != 0 {
+                    warn!("WARNING: {} periods out of range after retuning", clamped);
+                }
+            }
+            if song.frame_frequency != first.frame_frequency {
+                song = song.resample(first.frame_frequency, ResampleMode::PreserveRetriggers);
+            }
+            if song.version != version {
+                song.convert_to_ym6();
+            }
+            let mut renumber = [0u8;MAX_DD_SAMPLES];
+            if version != YmVersion::Ym2 {
+                for (sample, used) in song.used_dd_samples().into_iter().enumerate() {
+                    if !used {
+                        continue
+                    }
+                    let data = song.dd_samples.get(song.sample_data_range(sample)).unwrap_or(&[]);
+                    let found = (0..nsamples).find(|&index| {
+                        let start = index.checked_sub(1).map_or(0, |prev| dd_samples_ends[prev]);
+                        &dd_samples[start..dd_samples_ends[index]] == data
+                    });
+                    renumber[sample] = match found {
+                        Some(index) => index as u8,
+                        None if nsamples == MAX_DD_SAMPLES => {
+                            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                      "too many digi-drum samples"))
+                        }
+                        None => {
+                            dd_samples.extend_from_slice(data);
+                            dd_samples_ends[nsamples] = dd_samples.len();
+                            nsamples += 1;
+                            (nsamples - 1) as u8
+                        }
+                    };
+                }
+                song.renumber_dd_samples(&renumber);
+            }
+            loop_frame = frames.len() + song.loop_frame as usize;
+            frames.extend_from_slice(&song.frames);
+        }
+        if version == YmVersion::Ym2 {
+            dd_samples = first.dd_samples.to_vec();
+        }
+
+        let title = songs.iter().map(|song| song.title.as_str())
+                         .filter(|title| !title.is_empty())
+                         .collect::<Vec<_>>().join(" / ");
+        let mut authors: Vec<&str> = Vec::new();
+        for song in songs.iter() {
+            if !song.author.is_empty() && !authors.contains(&song.author.as_str()) {
+                authors.push(&song.author);
+            }
+        }
+        Ok(YmSong::new(version, frames.into_boxed_slice(), loop_frame as u32, title, first.created)
+                  .with_meta(authors.join(", "), first.comments.clone())
+                  .with_samples(first.song_attrs, dd_samples.into_boxed_slice(), dd_samples_ends)
+                  .with_frequency(first.chipset_frequency, first.frame_frequency))
+    }
+
+    /// Returns the indexes of volume registers that contain the number of a started `DIGI-DRUM`
+    /// sample in the given `frame`.
+    pub(super) fn dd_sample_regs(&self, frame: &YmFrame) -> [Option<usize>;2] {
+        let vol_reg = |chan: u8| (VOL_A_REG + chan) as usize;
+        match self.version {
+            YmVersion::Ym2|YmVersion::Ym3 => [None, None],
+            YmVersion::Ym4|YmVersion::Ym5 => [None, frame.fx1().dd_channel().map(vol_reg)],
+            YmVersion::Ym6 => [frame.fx0(), frame.fx1()].map(|flags|
+                match flags.fx6_channel() {
+                    Some((FxType::DigiDrum, chan)) => Some(vol_reg(chan)),
+                    _ => None
+                }
+            )
+        }
+    }
+
+    /// Returns which of the `DIGI-DRUM` samples are being started in frames.
+    pub(super) fn used_dd_samples(&self) -> [bool;MAX_DD_SAMPLES] {
+        let mut used = [false;MAX_DD_SAMPLES];
+        for frame in self.frames.iter() {
+            for reg in self.dd_sample_regs(frame).into_iter().flatten() {
+                used[(frame.data[reg] & 0x1f) as usize] = true;
+            }
+        }
+        used
+    }
+
+    /// Removes the `DIGI-DRUM` samples that are not used by any frame and renumbers the rest.
+    pub(super) fn retain_used_dd_samples(&mut self) {
+        if self.version == YmVersion::Ym2 {
+            return
+        }
+        let mut renumber = [0u8;MAX_DD_SAMPLES];
+        let mut dd_samples = Vec::new();
+        let mut dd_samples_ends = [0usize;MAX_DD_SAMPLES];
+        let mut nsamples = 0;
+        for (sample, used) in self.used_dd_samples().into_iter().enumerate() {
+            if used {
+                let data = self.dd_samples.get(self.sample_data_range(sample)).unwrap_or(&[]);
+                dd_samples.extend_from_slice(data);
+                dd_samples_ends[nsamples] = dd_samples.len();
+                renumber[sample] = nsamples as u8;
+                nsamples += 1;
+            }
+        }
+        self.renumber_dd_samples(&renumber);
+        self.dd_samples = dd_samples.into_boxed_slice();
+        self.dd_samples_ends = dd_samples_ends;
+    }
+
+    /// Returns `true` if all the periods and volumes in the `frame` are `0`.
+    fn is_reset_frame(&self, frame: &YmFrame) -> bool {
+        let key = self.frame_state_key(frame);
+        key[..MIXER_REG as usize].iter()
+           .chain(&key[VOL_A_REG as usize..ENV_REG as usize])
+           .all(|&v| v == 0)
+    }
+
+    /// Changes the `DIGI-DRUM` sample numbers in frames to the ones found in `renumber`
+    /// at the index of the original sample number.
+    pub(super) fn renumber_dd_samples(&mut self, renumber: &[u8;MAX_DD_SAMPLES]) {
+        for index in 0..self.frames.len() {
+            let regs = self.dd_sample_regs(&self.frames[index]);
+            for reg in regs.into_iter().flatten() {
+                let sample = &mut self.frames[index].data[reg];
+                *sample = (*sample & 0xe0) | renumber[(*sample & 0x1f) as usize];
+            }
+        }
+    }
+
+    /// Converts the special effects encoding of `YM3!`, `YM4!` and `YM5!` frames to `YM6!`.
+    ///
+    /// The `SID voice` timer restart flag of `YM4!` and `YM5!` has no equivalent in `YM6!`
+    /// and is being dropped.
+    ///
+    /// # Panics
+    /// Panics if the song's version is `YM2!`.
+    pub(super) fn convert_to_ym6(&mut self) {
+        let fx_mask = FxCtrlFlags::FX_TYPE_MASK | FxCtrlFlags::CHAN_CONTROL_MASK;
+        let fx_mask = fx_mask.bits();
+        match self.version {
+            YmVersion::Ym2 => panic!("can't convert YM2! frames"),
+            YmVersion::Ym3 => {
+                for frame in self.frames.iter_mut() {
+                    frame.data[1] &= !fx_mask;
+                    frame.data[3] &= !fx_mask;
+                    frame.data[NOISE_PER_REG as usize] &= 0x1f;
+                    frame.data[VOL_A_REG as usize] &= 0x1f;
+                    frame.data[14] = 0;
+                    frame.data[15] = 0;
+                }
+            }
+            YmVersion::Ym4|YmVersion::Ym5 => {
+                for frame in self.frames.iter_mut() {
+                    let fx0 = frame.fx0() & FxCtrlFlags::CHAN_CONTROL_MASK;
+                    let mut fx1 = frame.fx1() & FxCtrlFlags::CHAN_CONTROL_MASK;
+                    if !fx1.is_empty() {
+                        fx1 |= FxCtrlFlags::FX_TYPE_DIGI_DRUM;
+                    }
+                    frame.data[1] = (frame.data[1] & !fx_mask) | fx0.bits();
+                    frame.data[3] = (frame.data[3] & !fx_mask) | fx1.bits();
+                }
+            }
+            YmVersion::Ym6 => {}
+        }
+        self.version = YmVersion::Ym6;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drum_song(samples: &[&[u8]], starts: &[u8]) -> YmSong {
+        let frames = starts.iter().map(|&sample| {
+            let mut frame = YmFrame::default();
+            frame.data[ENV_REG as usize] = 0xff;
+            // DIGI-DRUM on voice B, timer: 4 * 100
+            frame.data[3] = 0b0110_0000;
+            frame.data[VOL_A_REG as usize] = 0b0010_0000;
+            frame.data[VOL_B_REG as usize] = sample;
+            frame.data[15] = 100;
+            frame
+        }).collect();
+        let mut dd_samples_ends = [0;MAX_DD_SAMPLES];
+        let mut end = 0;
+        for (sample, data) in dd_samples_ends.iter_mut().zip(samples) {
+            end += data.len();
+            *sample = end;
+        }
+        YmSong::new(YmVersion::Ym6, frames, 0, String::new(), None)
+               .with_samples(SongAttributes::default(), samples.concat().into(), dd_samples_ends)
+    }
+
+    #[test]
+    fn concat_merges_samples() {
+        let one = drum_song(&[&[1, 2], &[3], &[4, 5, 6]], &[2, 0]);
+        let mut two = drum_song(&[&[4, 5, 6], &[7]], &[1, 0, 1]);
+        two.version = YmVersion::Ym5;
+        two.loop_frame = 1;
+        let song = YmSong::concat(&[one, two]).unwrap();
+        assert_eq!(song.version, YmVersion::Ym6);
+        assert_eq!(song.frames.len(), 5);
+        assert_eq!(song.loop_frame, 3);
+        assert_eq!(&song.dd_samples[..], &[1, 2, 4, 5, 6, 7]);
+        assert_eq!(song.dd_samples_ends[..3], [2, 5, 6]);
+        let samples: Vec<_> = song.frames.iter().map(|f| f.data[VOL_B_REG as usize] & 0x1f).collect();
+        assert_eq!(samples, [1, 0, 2, 1, 2]);
+        assert_eq!(song.frames[3].fx1().fx6_channel(), Some((FxType::DigiDrum, 1)));
+    }
+
+    #[test]
+    fn slice_works() {
+        let mut song = drum_song(&[&[1]], &[0, 0, 0, 0]);
+        song.frames[1].data[ENV_REG as usize] = 0x0e;
+        song.loop_frame = 3;
+        let slice = song.slice(2..);
+        assert_eq!(slice.frames.len(), 2);
+        assert_eq!(slice.loop_frame, 1);
+        assert_eq!(slice.frames[0].data[ENV_REG as usize], 0x0e);
+        assert_eq!(&slice.dd_samples[..], &[1]);
+    }
+
+    #[test]
+    fn split_subsongs_works() {
+        let mut song = drum_song(&[&[1], &[2], &[3]], &[2, 2, 0, 0, 2, 0, 1, 1]);
+        song.title = "Tunes".into();
+        for (index, frame) in song.frames.iter_mut().enumerate() {
+            frame.data[MIXER_REG as usize] = 0b111110;
+            match index {
+                2|3 => frame.data = [0;16],
+                5 => frame.data[..4].copy_from_slice(&[100, 0, 0, 0]),
+                _ => frame.data[0] = 100
+            }
+        }
+        let tunes = song.split_subsongs(2);
+        assert_eq!(tunes.len(), 2);
+        assert_eq!(tunes[0].title, "Tunes #1");
+        assert_eq!(tunes[0].frames.len(), 2);
+        assert_eq!(&tunes[0].dd_samples[..], &[3]);
+        assert_eq!(tunes[1].title, "Tunes #2");
+        assert_eq!(tunes[1].frames.len(), 4);
+        assert_eq!(&tunes[1].dd_samples[..], &[2, 3]);
+        let samples: Vec<_> = tunes[1].frames.iter().map(|f| f.data[VOL_B_REG as usize]).collect();
+        assert_eq!(samples, [1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn trim_silence_works() {
+        let frames = [0u8, 0, 8, 0x10, 0, 0].iter().map(|&vol| {
+            let mut frame = YmFrame::default();
+            frame.data[MIXER_REG as usize] = 0b111110;
+            frame.data[VOL_A_REG as usize] = vol;
+            frame.data[ENV_REG as usize] = if vol == 0 { 0x0e } else { 0xff };
+            frame
+        }).collect();
+        let mut song = YmSong::new(YmVersion::Ym5, frames, 1, String::new(), None);
+        assert_eq!(song.audible_frames(), [false, false, true, true, false, false]);
+        assert_eq!(song.trim_silence(), 2..4);
+        assert_eq!(song.frames.len(), 2);
+        assert_eq!(song.loop_frame, 0);
+        assert_eq!(song.frames[0].data[ENV_REG as usize], 0x0e);
+    }
+}
@@ -5,6 +5,7 @@ use arrayvec::ArrayVec;
 
 use lazy_static::lazy_static;
 
+pub const NOISE_PER_REG: u8 = 6;
 pub const MIXER_REG: u8 = 7;
 pub const VOL_A_REG: u8 = 8;
 pub const VOL_B_REG: u8 = 9;
@@ -0,0 +1,180 @@
+//! Songs embedded in the program image.
+//!
+//! The [StaticYmSong] can be created at compile time with the `include_ym!` macro from the
+//! `ym-file-parser-macros` crate, so the frames and samples are kept in the read-only memory,
+//! without parsing the file at runtime. The song is played with the [StaticYmPlayer], directly
+//! from the static data.
+use super::*;
+use super::player::SongData;
+
+/// The **YM** music file with all the data borrowed from the static memory.
+///
+/// The fields correspond to those of [YmSong].
+#[derive(Debug, Clone, Copy)]
+pub struct StaticYmSong {
+    /// YM-file version.
+    pub version: YmVersion,
+    /// The song attributes.
+    pub song_attrs: SongAttributes,
+    /// The song title or a file name.
+    pub title: &'static str,
+    /// The song author.
+    pub author: &'static str,
+    /// The comment.
+    pub comments: &'static str,
+    /// The name of the game, demo or other program the song comes from.
+    pub program: &'static str,
+    /// The name of the music editor the song was created with.
+    pub tracker: &'static str,
+    /// The year of the song release, `0` if unknown.
+    pub year: u16,
+    /// The type of the sound chip the song was created for.
+    pub chip_type: ChipType,
+    /// The intended stereo placement of the voice channels.
+    pub stereo: StereoLayout,
+    /// The number of cycles per second of the AY/YM chipset clock.
+    pub chipset_frequency: u32,
+    /// The number of frames played each second.
+    pub frame_frequency: u16,
+    /// The loop frame index.
+    pub loop_frame: u32,
+    /// The AY/YM state frames.
+    pub frames: &'static [YmFrame],
+    /// `DIGI-DRUM` samples.
+    pub dd_samples: &'static [u8],
+    /// `DIGI-DRUM` sample end indexes in [StaticYmSong::dd_samples].
+    pub dd_samples_ends: [usize;MAX_DD_SAMPLES],
+}
+
+/// The player of the [StaticYmSong], producing the AY/YM register changes directly from the
+/// borrowed song data.
+#[derive(Debug, Clone)]
+pub struct StaticYmPlayer<'a> {
+        song: &'a StaticYmSong,
+        state: PlayerState,
+}
+
+impl StaticYmSong {
+    /// Creates a new player of this song, starting at the first frame.
+    pub fn player(&self) -> StaticYmPlayer<'_> {
+        StaticYmPlayer { song: self, state: PlayerState::default() }
+    }
+
+    /// Returns the number of AY/YM chipset clock cycles of a single music frame.
+    pub fn frame_cycles(&self) -> f32 {
+        self.song_data().frame_cycles()
+    }
+
+    fn song_data(&self) -> SongData<'_> {
+        SongData {
+            version: self.version,
+            chipset_frequency: self.chipset_frequency,
+            frame_frequency: self.frame_frequency,
+            loop_frame: self.loop_frame,
+            frames: self.frames,
+            dd_samples: self.dd_samples,
+            dd_samples_ends: &self.dd_samples_ends
+        }
+    }
+
+    /// Returns the song duration.
+    pub fn song_duration(&self) -> Duration {
+        let seconds = self.frames.len() as f64 / self.frame_frequency as f64;
+        Duration::from_secs_f64(seconds)
+    }
+
+    /// Creates a new `YmSong` from the static data, e.g. to be edited or converted.
+    ///
+    /// The frames and samples are copied to the heap memory. To play the song use
+    /// [StaticYmSong::player] instead.
+    pub fn to_song(&self) -> YmSong {
+        let mut song = YmSong::new(self.version, self.frames.into(), self.loop_frame,
+                                   self.title.into(), None)
+                              .with_samples(self.song_attrs, self.dd_samples.into(), self.dd_samples_ends)
+                              .with_meta(self.author.into(), self.comments.into())
+                              .with_frequency(self.chipset_frequency, self.frame_frequency);
+        song.program = self.program.into();
+        song.tracker = self.tracker.into();
+        song.year = self.year;
+        song.chip_type = self.chip_type;
+        song.stereo = self.stereo;
+        song
+    }
+}
+
+impl<'a> StaticYmPlayer<'a> {
+    /// Returns the song being played.
+    pub fn song(&self) -> &'a StaticYmSong {
+        self.song
+    }
+
+    /// Resets the state of the player.
+    pub fn reset(&mut self) {
+        self.state.reset();
+    }
+
+    /// Returns the current frame cursor value.
+    pub fn cursor(&self) -> u32 {
+        self.state.cursor()
+    }
+
+    /// Produces the changes to the AY/YM chipset registers for the current frame indicated by
+    /// the cursor and advances the cursor forward one frame.
+    ///
+    /// This works exactly the same as [YmSong::produce_next_ay_frame].
+    pub fn produce_next_ay_frame<F: FnMut(f32, u8, u8)>(&mut self, rec: F) -> bool {
+        self.state.produce_next_ay_frame(&self.song.song_data(), rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static FRAMES: [YmFrame;2] = [
+        YmFrame { data: [1, 0, 0, 0, 0, 0, 0, 0x3e, 15, 0, 0, 0, 0, 0xff, 0, 0] },
+        YmFrame { data: [2, 0, 0, 0, 0, 0, 0, 0x3e, 15, 0, 0, 0, 0, 0xff, 0, 0] },
+    ];
+
+    static SONG: StaticYmSong = StaticYmSong {
+        version: YmVersion::Ym5,
+        song_attrs: SongAttributes::from_bits_retain(1),
+        title: "Title",
+        author: "Author",
+        comments: "",
+        program: "",
+        tracker: "",
+        year: 0,
+        chip_type: ChipType::Ym,
+        stereo: StereoLayout::Mono,
+        chipset_frequency: 2_000_000,
+        frame_frequency: 50,
+        loop_frame: 1,
+        frames: &FRAMES,
+        dd_samples: &[0, 8, 15],
+        dd_samples_ends: [3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                          0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    };
+
+    #[test]
+    fn static_song_works() {
+        assert_eq!(SONG.song_duration(), Duration::from_millis(40));
+        let song = SONG.to_song();
+        assert_eq!((song.title.as_str(), song.author.as_str()), ("Title", "Author"));
+        assert_eq!(song.loop_frame, 1);
+        assert_eq!(song.frames[1].data[0], 2);
+        assert_eq!(&song.dd_samples[song.sample_data_range(0)], &[0, 8, 15]);
+        assert!(song.song_attrs.is_interleaved());
+
+        let mut song = song;
+        let mut player = SONG.player();
+        for _ in 0..3 {
+            let (mut expected, mut changes) = (Vec::new(), Vec::new());
+            let looped = song.produce_next_ay_frame(|ts, reg, val| expected.push((ts, reg, val)));
+            assert_eq!(player.produce_next_ay_frame(|ts, reg, val| changes.push((ts, reg, val))), looped);
+            assert_eq!(changes, expected);
+            assert_eq!(player.cursor(), song.cursor());
+        }
+        assert_eq!(player.cursor(), 1);
+    }
+}
@@ -10,7 +10,7 @@ pub enum FxChannel {
     RunOnC = 3,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub enum FxType {
     SidVoice = 0,
@@ -25,6 +25,7 @@ bitflags! {
         const INTERLEAVED     = 0x0000_0001;
         const DIGIDRUM_SIGNED = 0x0000_0002;
         const DIGIDRUM_4BIT   = 0x0000_0004;
+        const LOOP_MODE       = 0x0000_0010;
     }
 }
 
@@ -60,6 +61,11 @@ impl SongAttributes {
     pub fn is_signed(self) -> bool {
         self.intersects(SongAttributes::DIGIDRUM_SIGNED)
     }
+
+    /// Returns `true` if the sample based song should be played in a loop.
+    pub fn is_loop_mode(self) -> bool {
+        self.intersects(SongAttributes::LOOP_MODE)
+    }
 }
 
 impl FxCtrlFlags {
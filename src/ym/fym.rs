@@ -0,0 +1,128 @@
+//! FYM (zlib compressed register dump) file conversion.
+use std::io::{self, Read, Write};
+
+use miniz_oxide::{deflate, inflate};
+
+use super::*;
+use super::psg::clear_unused_bits;
+
+/// The size of the fixed part of the header.
+const FYM_HEADER_SIZE: usize = 20;
+/// The number of registers of a single frame in the register dump.
+const FYM_REGS: usize = 14;
+const FYM_COMPRESSION_LEVEL: u8 = 9;
+/// The limit of the decompressed file size, more than a day of register data at 50 Hz.
+const FYM_MAX_SIZE: usize = 64 << 20;
+
+impl YmSong {
+    /// Attempts to parse a `FYM` file from the given stream source.
+    ///
+    /// The new song is a `YM6!` song without special effects, with the loop, frequencies, title
+    /// and author taken from the file's header.
+    pub fn parse_fym<R: Read>(mut rd: R) -> io::Result<YmSong> {
+        let mut packed = Vec::new();
+        rd.read_to_end(&mut packed)?;
+        let data = inflate::decompress_to_vec_zlib_with_limit(&packed, FYM_MAX_SIZE)
+                   .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        if data.len() < FYM_HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely"))
+        }
+        let dword = |index: usize| {
+            u32::from_le_bytes(data[index * 4..index * 4 + 4].try_into().unwrap())
+        };
+        let header_size = dword(0) as usize;
+        let nframes = dword(1) as usize;
+        let loop_frame = dword(2);
+        let chipset_frequency = dword(3);
+        let frame_frequency = u16::try_from(dword(4)).ok().filter(|&freq| freq != 0);
+        let dump = nframes.checked_mul(FYM_REGS).and_then(|dump_size|
+            data.get(header_size..).filter(|dump| dump.len() >= dump_size)
+        );
+        let (Some(frame_frequency), Some(dump)) = (frame_frequency, dump) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid FYM header"))
+        };
+        if nframes == 0 || chipset_frequency == 0 || header_size < FYM_HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid FYM header"))
+        }
+        let mut texts = data[FYM_HEADER_SIZE..header_size]
+                        .split(|&b| b == 0)
+                        .map(|text| String::from_utf8_lossy(text).into_owned());
+        let title = texts.next().unwrap_or_default();
+        let author = texts.next().unwrap_or_default();
+        let frames = (0..nframes).map(|index| {
+            let mut frame = YmFrame::default();
+            for (reg, val) in frame.data[..FYM_REGS].iter_mut().enumerate() {
+                *val = dump[reg * nframes + index];
+            }
+            clear_unused_bits(&mut frame.data);
+            frame
+        }).collect();
+        Ok(YmSong::new(YmVersion::Ym6, frames, loop_frame, title, None)
+                  .with_meta(author, String::new())
+                  .with_frequency(chipset_frequency, frame_frequency))
+    }
+
+    /// Writes the song as a `FYM` file to the given stream.
+    ///
+    /// The YM-specific special effects are dropped, the same way as in [YmSong::write_psg].
+    pub fn write_fym<W: Write>(&self, mut wr: W) -> io::Result<()> {
+        let nframes = u32::try_from(self.frames.len())
+                      .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut texts = Vec::new();
+        for text in [&self.title, &self.author] {
+            let bytes = text.as_bytes();
+            let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            texts.extend_from_slice(&bytes[..len]);
+            texts.push(0);
+        }
+        let header_size = (FYM_HEADER_SIZE + texts.len()) as u32;
+        let mut data = Vec::with_capacity(header_size as usize + self.frames.len() * FYM_REGS);
+        for value in [header_size, nframes, self.loop_frame, self.chipset_frequency, self.frame_frequency as u32] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&texts);
+        let dump_start = data.len();
+        data.resize(dump_start + self.frames.len() * FYM_REGS, 0);
+        let dump = &mut data[dump_start..];
+        for (index, regs) in self.ay_register_dump().into_iter().enumerate() {
+            for (reg, val) in regs.into_iter().enumerate() {
+                dump[reg * self.frames.len() + index] = val;
+            }
+        }
+        wr.write_all(&deflate::compress_to_vec_zlib(&data, FYM_COMPRESSION_LEVEL))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fym_roundtrip_works() {
+        let frames = (0..100u32).map(|n| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(1, n as u16 * 3);
+            frame.data[VOL_B_REG as usize] = 0x10;
+            frame.data[ENV_REG as usize] = if n % 10 == 0 { 0x08 } else { 0xff };
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 7, "Title".into(), None)
+                         .with_meta("Author".into(), String::new())
+                         .with_frequency(1_750_000, 60);
+        let mut fym = Vec::new();
+        song.write_fym(&mut fym).unwrap();
+        let parsed = YmSong::parse_fym(&fym[..]).unwrap();
+        assert_eq!((parsed.title.as_str(), parsed.author.as_str()), ("Title", "Author"));
+        assert_eq!((parsed.chipset_frequency, parsed.frame_frequency), (1_750_000, 60));
+        assert_eq!(parsed.loop_frame, 7);
+        assert_eq!(parsed.frames.len(), 100);
+        for (a, b) in parsed.frames.iter().zip(song.frames.iter()) {
+            assert_eq!(a.data, b.data);
+        }
+
+        let mut data = inflate::decompress_to_vec_zlib(&fym).unwrap();
+        data[..4].copy_from_slice(&12u32.to_le_bytes());
+        let fym = deflate::compress_to_vec_zlib(&data, FYM_COMPRESSION_LEVEL);
+        assert_eq!(YmSong::parse_fym(&fym[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
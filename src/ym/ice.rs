@@ -0,0 +1,210 @@
+//! ICE! 2.x depacker.
+//!
+//! The Atari "ICE!" packed data consists of a 12 bytes header: the `ICE!` identifier, the size
+//! of the packed data including the header and the size of the unpacked data, both as 32-bit
+//! big-endian integers, followed by the packed bit stream, which is decoded from the end
+//! towards the beginning, filling the output from the end.
+use std::io;
+
+/// The identifier of the ICE! packed data.
+pub(super) const ICE_MAGIC: &[u8;4] = b"ICE!";
+
+const ICE_HEADER_SIZE: usize = 12;
+/// The bit counts of the literal run length codes.
+const LITERAL_BITS: [u32;5] = [2, 2, 3, 8, 15];
+/// The run length added for each of the literal run length codes.
+const LITERAL_BASE: [usize;5] = [1, 4, 7, 14, 269];
+/// The longest string length.
+const MAX_STRING_LEN: usize = 10 + (1 << 10) - 1;
+/// The largest possible ratio of the unpacked to the packed size: a single bit code of
+/// the longest string.
+const MAX_RATIO: usize = 8 * MAX_STRING_LEN;
+/// The default number of 4 word blocks of the ICE! picture option.
+const PICTURE_BLOCKS: usize = 4000;
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u8,
+}
+
+impl BitReader<'_> {
+    fn byte(&mut self) -> io::Result<u8> {
+        if self.pos == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "ICE! data ended prematurely"))
+        }
+        self.pos -= 1;
+        Ok(self.data[self.pos])
+    }
+
+    fn bit(&mut self) -> io::Result<bool> {
+        let carry = self.bits & 0x80 != 0;
+        self.bits <<= 1;
+        if self.bits != 0 {
+            return Ok(carry)
+        }
+        // the lowest set bit of the bit buffer is the end marker
+        let byte = self.byte()?;
+        self.bits = (byte << 1) | carry as u8;
+        Ok(byte & 0x80 != 0)
+    }
+
+    fn bits(&mut self, count: u32) -> io::Result<usize> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.bit()? as usize;
+        }
+        Ok(value)
+    }
+
+    /// Counts the consecutive `1` bits up to `max`.
+    fn ones(&mut self, max: usize) -> io::Result<usize> {
+        let mut count = 0;
+        while count < max && self.bit()? {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Returns `true` if the `data` starts with the ICE! header.
+pub(super) fn is_ice_packed(data: &[u8]) -> bool {
+    data.starts_with(ICE_MAGIC)
+}
+
+/// Unpacks the ICE! packed `data`, including the header.
+pub(super) fn depack(data: &[u8]) -> io::Result<Vec<u8>> {
+    if data.len() < ICE_HEADER_SIZE || !is_ice_packed(data) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an ICE! packed data"))
+    }
+    let dword = |index: usize| u32::from_be_bytes(data[index..index + 4].try_into().unwrap()) as usize;
+    let packed_size = dword(4);
+    let size = dword(8);
+    if packed_size < ICE_HEADER_SIZE + 1 || packed_size > data.len() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid ICE! packed size"))
+    }
+    if size > (packed_size - ICE_HEADER_SIZE).saturating_mul(MAX_RATIO) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid ICE! unpacked size"))
+    }
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid ICE! packed data");
+    let mut bits = BitReader { data: &data[ICE_HEADER_SIZE..packed_size], pos: packed_size - ICE_HEADER_SIZE, bits: 0 };
+    bits.bits = bits.byte()?;
+    let mut out = Vec::new();
+    out.try_reserve_exact(size).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    out.resize(size, 0u8);
+    let mut pos = size;
+    loop {
+        // literal bytes
+        if bits.bit()? {
+            let mut count = 1;
+            if bits.bit()? {
+                for (&nbits, &base) in LITERAL_BITS.iter().zip(LITERAL_BASE.iter()) {
+                    let value = bits.bits(nbits)?;
+                    count = value + base + 1;
+                    if value != (1 << nbits) - 1 {
+                        break
+                    }
+                }
+            }
+            if count > pos {
+                return Err(invalid())
+            }
+            for _ in 0..count {
+                pos -= 1;
+                out[pos] = bits.byte()?;
+            }
+        }
+        if pos == 0 {
+            break
+        }
+        // string
+        let len = match bits.ones(4)? {
+            0 => 2,
+            1 => 3,
+            2 => 4 + bits.bits(1)?,
+            3 => 6 + bits.bits(2)?,
+            _ => 10 + bits.bits(10)?
+        };
+        let distance = if len == 2 {
+            match bits.bit()? {
+                false => bits.bits(6)? + 1,
+                true => bits.bits(9)? + 0x41
+            }
+        }
+        else {
+            let offset = match bits.ones(2)? {
+                0 => bits.bits(8)? as isize + 0x1f,
+                1 => bits.bits(5)? as isize - 1,
+                _ => bits.bits(12)? as isize + 0x11f
+            };
+            if offset < 0 { 1 } else { len + offset as usize }
+        };
+        if len > pos || pos - 1 + distance >= size {
+            return Err(invalid())
+        }
+        for _ in 0..len {
+            pos -= 1;
+            out[pos] = out[pos + distance];
+        }
+    }
+    if bits.bit()? {
+        let blocks = match bits.bit()? {
+            true => bits.bits(15)? + 1,
+            false => PICTURE_BLOCKS
+        };
+        if blocks * 8 > size {
+            return Err(invalid())
+        }
+        unpack_picture(&mut out[size - blocks * 8..]);
+    }
+    Ok(out)
+}
+
+/// Reverts the bit plane transformation of the ICE! picture option.
+fn unpack_picture(data: &mut [u8]) {
+    for block in data.chunks_exact_mut(8) {
+        let mut planes = [0u16;4];
+        for word in (0..4).rev() {
+            let mut w = u16::from_be_bytes([block[word * 2], block[word * 2 + 1]]);
+            for _ in 0..4 {
+                for plane in planes.iter_mut() {
+                    *plane = (*plane << 1) | (w >> 15);
+                    w <<= 1;
+                }
+            }
+        }
+        for (chunk, plane) in block.chunks_exact_mut(2).zip(planes) {
+            chunk.copy_from_slice(&plane.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ice_depack_works() {
+        let mut ice = b"ICE!".to_vec();
+        ice.extend_from_slice(&17u32.to_be_bytes());
+        ice.extend_from_slice(&6u32.to_be_bytes());
+        ice.extend_from_slice(&[0x04, 0x08, b'a', b'b', 0xc1]);
+        assert_eq!(depack(&ice).unwrap(), b"ababab");
+        assert!(depack(&ice[..16]).is_err());
+        ice[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(depack(&ice).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn ice_depack_picture_works() {
+        let mut ice = b"ICE!".to_vec();
+        ice.extend_from_slice(&24u32.to_be_bytes());
+        ice.extend_from_slice(&8u32.to_be_bytes());
+        // 8 literal bytes followed by the picture option with a custom count of 1 block
+        ice.extend_from_slice(&[0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0xfd]);
+        assert_eq!(depack(&ice).unwrap(), [0x00, 0x0f, 0x00, 0x0f, 0x00, 0x0f, 0x00, 0x0f]);
+        // the block count exceeding the unpacked size
+        ice[22] = 0x3f;
+        assert_eq!(depack(&ice).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
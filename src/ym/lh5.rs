@@ -0,0 +1,321 @@
+//! A raw `-lh5-` stream encoder, without the LHA archive envelope.
+//!
+//! The `VTX` files contain the register data compressed with the `-lh5-` method but without the LHA
+//! header. The stream is decoded with the `delharc` decoder, but it can only decode, so the encoder
+//! is implemented here.
+use super::bits::BitWriter;
+
+const DICBIT: u32 = 13;
+/// The largest match distance.
+const MAX_DISTANCE: usize = (1 << DICBIT) - 1;
+const MAX_MATCH: usize = 256;
+const THRESHOLD: usize = 3;
+/// The number of literal and match length codes.
+const NC: usize = 256 + MAX_MATCH + 2 - THRESHOLD;
+const CBIT: u32 = 9;
+/// The number of code length codes.
+const NT: usize = 16 + 3;
+const TBIT: u32 = 5;
+/// The number of distance codes.
+const NP: usize = DICBIT as usize + 1;
+const PBIT: u32 = 4;
+const MAX_CODE_LEN: u8 = 16;
+/// The number of codes in a single block.
+const BLOCK_CODES: usize = 0x4000;
+/// The largest possible ratio of the decompressed to the compressed size: a single bit code
+/// of the longest match.
+pub(super) const MAX_RATIO: usize = 8 * MAX_MATCH;
+const HASH_SIZE: usize = 1 << 13;
+const MAX_CHAIN: usize = 128;
+
+/// Compresses `data` into the `-lh5-` stream.
+pub(super) fn compress(data: &[u8]) -> Vec<u8> {
+    let codes = find_matches(data);
+    let mut wr = BitWriter::default();
+    for block in codes.chunks(BLOCK_CODES) {
+        write_block(block, &mut wr);
+    }
+    wr.finish()
+}
+
+/// A literal byte or a match of the `length` with the `offset` being the distance minus `1`.
+#[derive(Debug, Clone, Copy)]
+enum Code {
+    Literal(u8),
+    Match { length: u16, offset: u16 }
+}
+
+/// Finds the longest matches with the hash chains of 3-byte prefixes.
+fn find_matches(data: &[u8]) -> Vec<Code> {
+    let hash = |pos: usize| {
+        let h = (data[pos] as usize) << 10 ^ (data[pos + 1] as usize) << 5 ^ data[pos + 2] as usize;
+        h & (HASH_SIZE - 1)
+    };
+    let mut head = vec![usize::MAX;HASH_SIZE];
+    let mut prev = vec![usize::MAX;data.len()];
+    let mut codes = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let max_len = MAX_MATCH.min(data.len() - pos);
+        let mut best = (0, 0);
+        if max_len >= THRESHOLD {
+            let mut candidate = head[hash(pos)];
+            let mut chain = 0;
+            while candidate != usize::MAX && pos - candidate <= MAX_DISTANCE && chain < MAX_CHAIN {
+                let len = data[candidate..].iter().zip(&data[pos..pos + max_len])
+                                           .take_while(|(a, b)| a == b).count();
+                if len > best.0 {
+                    best = (len, pos - candidate);
+                    if len == max_len {
+                        break
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+        let step = if best.0 >= THRESHOLD {
+            codes.push(Code::Match { length: best.0 as u16, offset: (best.1 - 1) as u16 });
+            best.0
+        }
+        else {
+            codes.push(Code::Literal(data[pos]));
+            1
+        };
+        for p in (pos..pos + step).take_while(|p| p + THRESHOLD <= data.len()) {
+            let h = hash(p);
+            prev[p] = head[h];
+            head[h] = p;
+        }
+        pos += step;
+    }
+    codes
+}
+
+fn bit_length(value: u32) -> u32 {
+    u32::BITS - value.leading_zeros()
+}
+
+fn c_symbol(code: &Code) -> usize {
+    match *code {
+        Code::Literal(byte) => byte as usize,
+        Code::Match { length, .. } => length as usize - THRESHOLD + 256
+    }
+}
+
+fn write_block(codes: &[Code], wr: &mut BitWriter) {
+    let mut c_freq = [0u32;NC];
+    let mut p_freq = [0u32;NP];
+    for code in codes {
+        c_freq[c_symbol(code)] += 1;
+        if let Code::Match { offset, .. } = *code {
+            p_freq[bit_length(offset as u32) as usize] += 1;
+        }
+    }
+    let c_len = huffman_lengths(&c_freq);
+    let p_len = huffman_lengths(&p_freq);
+    let c_code = canonical_codes(&c_len);
+    let p_code = canonical_codes(&p_len);
+
+    wr.put(16, codes.len() as u32);
+    match single_symbol(&c_freq) {
+        Some(sym) => {
+            wr.put(TBIT, 0);
+            wr.put(TBIT, 0);
+            wr.put(CBIT, 0);
+            wr.put(CBIT, sym as u32);
+        }
+        None => {
+            let mut t_freq = [0u32;NT];
+            c_len_runs(&c_len, |sym, _| t_freq[sym] += 1);
+            let t_len = huffman_lengths(&t_freq);
+            let t_code = canonical_codes(&t_len);
+            match single_symbol(&t_freq) {
+                Some(sym) => {
+                    wr.put(TBIT, 0);
+                    wr.put(TBIT, sym as u32);
+                }
+                None => write_pt_len(&t_len, TBIT, Some(3), wr)
+            }
+            let n = c_len.iter().rposition(|&len| len != 0).map_or(0, |n| n + 1);
+            wr.put(CBIT, n as u32);
+            c_len_runs(&c_len, |sym, extra| {
+                wr.put(t_len[sym] as u32, t_code[sym]);
+                match (sym, extra) {
+                    (1, Some(count)) => wr.put(4, count),
+                    (2, Some(count)) => wr.put(CBIT, count),
+                    _ => {}
+                }
+            });
+        }
+    }
+    match single_symbol(&p_freq) {
+        Some(sym) => {
+            wr.put(PBIT, 0);
+            wr.put(PBIT, sym as u32);
+        }
+        None => write_pt_len(&p_len, PBIT, None, wr)
+    }
+
+    for code in codes {
+        let sym = c_symbol(code);
+        wr.put(c_len[sym] as u32, c_code[sym]);
+        if let Code::Match { offset, .. } = *code {
+            let nbits = bit_length(offset as u32);
+            wr.put(p_len[nbits as usize] as u32, p_code[nbits as usize]);
+            if nbits > 1 {
+                wr.put(nbits - 1, offset as u32 - (1 << (nbits - 1)));
+            }
+        }
+    }
+}
+
+fn single_symbol(freq: &[u32]) -> Option<usize> {
+    let mut used = freq.iter().enumerate().filter(|&(_, &f)| f != 0).map(|(sym, _)| sym);
+    match (used.next(), used.next()) {
+        (Some(sym), None) => Some(sym),
+        (None, _) => Some(0),
+        _ => None
+    }
+}
+
+/// Encodes the literal and length code lengths as the code length symbols, calling `emit` with each
+/// symbol and the extra bits value of the zero runs.
+fn c_len_runs<F: FnMut(usize, Option<u32>)>(c_len: &[u8], mut emit: F) {
+    let n = c_len.iter().rposition(|&len| len != 0).map_or(0, |n| n + 1);
+    let mut i = 0;
+    while i < n {
+        let len = c_len[i];
+        i += 1;
+        if len != 0 {
+            emit(len as usize + 2, None);
+            continue
+        }
+        let mut count = 1;
+        while i < n && c_len[i] == 0 {
+            i += 1;
+            count += 1;
+        }
+        match count {
+            1|2 => (0..count).for_each(|_| emit(0, None)),
+            3..=18 => emit(1, Some(count - 3)),
+            19 => {
+                emit(0, None);
+                emit(1, Some(15));
+            }
+            _ => emit(2, Some(count - 20))
+        }
+    }
+}
+
+fn write_pt_len(lengths: &[u8], nbit: u32, special: Option<usize>, wr: &mut BitWriter) {
+    let n = lengths.iter().rposition(|&len| len != 0).map_or(0, |n| n + 1);
+    wr.put(nbit, n as u32);
+    let mut i = 0;
+    while i < n {
+        let len = lengths[i] as u32;
+        i += 1;
+        if len <= 6 {
+            wr.put(3, len);
+        }
+        else {
+            wr.put(len - 3, (1 << (len - 3)) - 2);
+        }
+        if Some(i) == special {
+            let zeros = lengths[i..n.min(6)].iter().take_while(|&&len| len == 0).count();
+            wr.put(2, zeros as u32);
+            i += zeros;
+        }
+    }
+}
+
+/// Calculates the Huffman code lengths limited to [MAX_CODE_LEN] bits.
+///
+/// Unused symbols get the length `0`. A single used symbol gets the length `0` too, as it's
+/// encoded without bits.
+fn huffman_lengths(freq: &[u32]) -> Vec<u8> {
+    let mut freq = freq.to_vec();
+    loop {
+        let lengths = unlimited_huffman_lengths(&freq);
+        if lengths.iter().all(|&len| len <= MAX_CODE_LEN) {
+            return lengths
+        }
+        for f in freq.iter_mut().filter(|f| **f != 0) {
+            *f = (*f >> 1).max(1);
+        }
+    }
+}
+
+fn unlimited_huffman_lengths(freq: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0u8;freq.len()];
+    // nodes: (weight, parent)
+    let mut nodes: Vec<(u64, usize)> = Vec::new();
+    let mut heap = std::collections::BinaryHeap::new();
+    let mut leaves = Vec::new();
+    for (sym, &f) in freq.iter().enumerate().filter(|&(_, &f)| f != 0) {
+        heap.push(core::cmp::Reverse((f as u64, nodes.len())));
+        leaves.push((sym, nodes.len()));
+        nodes.push((f as u64, usize::MAX));
+    }
+    if leaves.len() < 2 {
+        return lengths
+    }
+    while heap.len() > 1 {
+        let core::cmp::Reverse((wa, a)) = heap.pop().unwrap();
+        let core::cmp::Reverse((wb, b)) = heap.pop().unwrap();
+        let parent = nodes.len();
+        nodes.push((wa + wb, usize::MAX));
+        nodes[a].1 = parent;
+        nodes[b].1 = parent;
+        heap.push(core::cmp::Reverse((wa + wb, parent)));
+    }
+    for (sym, mut node) in leaves {
+        let mut depth = 0usize;
+        while nodes[node].1 != usize::MAX {
+            node = nodes[node].1;
+            depth += 1;
+        }
+        lengths[sym] = depth.min(u8::MAX as usize) as u8;
+    }
+    lengths
+}
+
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut counts = [0u32;MAX_CODE_LEN as usize + 2];
+    for &len in lengths {
+        counts[len as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut next = [0u32;MAX_CODE_LEN as usize + 2];
+    for len in 1..=MAX_CODE_LEN as usize {
+        next[len + 1] = (next[len] + counts[len]) << 1;
+    }
+    lengths.iter().map(|&len| {
+        if len == 0 {
+            return 0
+        }
+        let code = next[len as usize];
+        next[len as usize] += 1;
+        code
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use delharc::decode::{Decoder, Lh5Decoder};
+    use super::*;
+
+    #[test]
+    fn lh5_roundtrip_works() {
+        let mut data: Vec<u8> = (0..20000u32).map(|n| ((n * n) >> 7) as u8 & 0x1f).collect();
+        data.extend(core::iter::repeat_n(7u8, 3000));
+        data.extend(b"abcabcabcd");
+        for data in [&data[..], b"", b"x", b"xxxxxxxx"] {
+            let packed = compress(data);
+            let mut unpacked = vec![0u8;data.len()];
+            Lh5Decoder::new(&packed[..]).fill_buffer(&mut unpacked).unwrap();
+            assert_eq!(unpacked, data);
+        }
+        assert!(compress(&data).len() < data.len() / 2);
+    }
+}
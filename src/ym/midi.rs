@@ -0,0 +1,722 @@
+//! Standard MIDI File conversion.
+use std::io::{self, Read};
+
+use super::*;
+use super::notes::{FrameNote, PitchSource};
+
+/// General MIDI percussion keys assigned to `DIGI-DRUM` samples by the sample number modulo
+/// the length of this table.
+pub const DD_SAMPLE_DRUM_KEYS: [u8;8] = [
+    36, // Bass Drum 1
+    38, // Acoustic Snare
+    42, // Closed Hi-Hat
+    46, // Open Hi-Hat
+    49, // Crash Cymbal 1
+    45, // Low Tom
+    48, // Hi-Mid Tom
+    39, // Hand Clap
+];
+
+/// The MIDI channel of the percussion track.
+pub const PERCUSSION_CHANNEL: u8 = 9;
+
+/// The pitch bend range in semitones, set up on each voice channel.
+const BEND_RANGE: f32 = 12.0;
+const BEND_CENTER: u16 = 0x2000;
+/// General MIDI program: Lead 1 (square).
+const VOICE_PROGRAM: u8 = 80;
+const MICROS_PER_QUARTER: u32 = 500_000;
+const NOISE_VELOCITY: u8 = 90;
+const DD_VELOCITY: u8 = 110;
+
+/// Which note to play when more notes are held on a single voice channel.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NotePriority {
+    /// The most recently started note.
+    #[default]
+    Last,
+    /// The highest note.
+    Highest,
+    /// The lowest note.
+    Lowest,
+}
+
+/// How the notes of the drum channel are converted by [YmSong::parse_midi].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum DrumMapping {
+    /// The drum channel is ignored.
+    #[default]
+    Ignore,
+    /// Each drum note plays a short burst of noise with the period depending on the key.
+    Noise,
+    /// Each drum note starts a `DIGI-DRUM` sample found at the position of the key in
+    /// [DD_SAMPLE_DRUM_KEYS]. Other keys are ignored.
+    DigiDrum {
+        /// 4-bit samples, up to [MAX_DD_SAMPLES].
+        samples: Vec<Box<[u8]>>,
+        /// The sample playback rate in Hz.
+        sample_rate: u32,
+    },
+}
+
+/// Options for [YmSong::parse_midi].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MidiImportOptions {
+    /// The voice channel `[0, 2]` for each of the 16 MIDI channels, `None` ignores the MIDI channel.
+    pub channel_map: [Option<u8>;16],
+    /// Which note to play when more notes are held on a single voice channel.
+    pub priority: NotePriority,
+    /// The AY/YM chipset clock frequency of the new song.
+    pub chipset_frequency: u32,
+    /// The frame frequency of the new song.
+    pub frame_frequency: u16,
+    /// The MIDI channel with drums.
+    pub drum_channel: Option<u8>,
+    /// The voice channel `[0, 2]` playing drums.
+    pub drum_voice: u8,
+    /// How the drums are converted.
+    pub drums: DrumMapping,
+}
+
+impl Default for MidiImportOptions {
+    /// Maps MIDI channels 0, 1 and 2 to voices A, B and C, ignores drums on MIDI channel 9 and
+    /// creates songs for the Atari ST clock at 50 Hz.
+    fn default() -> Self {
+        let mut channel_map = [None;16];
+        channel_map[..3].copy_from_slice(&[Some(0), Some(1), Some(2)]);
+        MidiImportOptions {
+            channel_map,
+            priority: NotePriority::Last,
+            chipset_frequency: DEFAULT_CHIPSET_FREQUENCY,
+            frame_frequency: DEFAULT_FRAME_FREQUENCY,
+            drum_channel: Some(PERCUSSION_CHANNEL),
+            drum_voice: 2,
+            drums: DrumMapping::Ignore,
+        }
+    }
+}
+
+/// A channel event of a parsed MIDI file.
+#[derive(Debug, Clone, Copy)]
+struct MidiEvent {
+    tick: u64,
+    status: u8,
+    data: [u8;2],
+}
+
+/// A note held on a voice channel.
+#[derive(Debug, Clone, Copy)]
+struct HeldNote {
+    chan: u8,
+    key: u8,
+    velocity: u8,
+    /// The frame index of the note start.
+    onset: u32,
+    /// Released in the same frame it was started.
+    released: bool,
+}
+
+/// A single MIDI track being built from chronological events.
+#[derive(Debug, Default)]
+struct Track {
+    data: Vec<u8>,
+    last_tick: u32,
+}
+
+/// The state of a note being played on a voice track.
+#[derive(Debug, Clone, Copy)]
+struct PlayingNote {
+    key: u8,
+    source: PitchSource,
+    velocity_volume: u8,
+    volume: u8,
+    pitch: f32,
+    bend: u16,
+    expression: u8,
+}
+
+impl Track {
+    fn event(&mut self, tick: u32, data: &[u8]) {
+        write_vlq(&mut self.data, tick - self.last_tick);
+        self.data.extend_from_slice(data);
+        self.last_tick = tick;
+    }
+
+    fn meta(&mut self, tick: u32, kind: u8, data: &[u8]) {
+        self.event(tick, &[0xff, kind]);
+        write_vlq(&mut self.data, data.len() as u32);
+        self.data.extend_from_slice(data);
+    }
+
+    fn name(&mut self, name: &str) {
+        if !name.is_empty() {
+            self.meta(0, 0x03, name.as_bytes());
+        }
+    }
+
+    fn finish(mut self, tick: u32, out: &mut Vec<u8>) {
+        self.meta(tick.max(self.last_tick), 0x2f, &[]);
+        out.extend_from_slice(b"MTrk");
+        out.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.data);
+    }
+}
+
+/// Writes a MIDI variable-length quantity.
+pub(super) fn write_vlq(out: &mut Vec<u8>, mut value: u32) {
+    let mut buf = [0u8;5];
+    let mut index = buf.len() - 1;
+    buf[index] = (value & 0x7f) as u8;
+    value >>= 7;
+    while value != 0 {
+        index -= 1;
+        buf[index] = 0x80 | (value & 0x7f) as u8;
+        value >>= 7;
+    }
+    out.extend_from_slice(&buf[index..]);
+}
+
+fn velocity(volume: u8) -> u8 {
+    ((volume.min(15) as u32 * 127 + 7) / 15).max(1) as u8
+}
+
+fn exact_pitch(note: &FrameNote) -> f32 {
+    note.note as f32 + note.cents / 100.0
+}
+
+fn bend_value(semitones: f32) -> u16 {
+    let bend = BEND_CENTER as f32 + semitones / BEND_RANGE * BEND_CENTER as f32;
+    bend.round().clamp(0.0, 0x3fff as f32) as u16
+}
+
+/// Picks the General MIDI percussion key for the noise `period`.
+fn noise_drum_key(period: u8) -> u8 {
+    match period {
+        0..=5 => 42,  // Closed Hi-Hat
+        6..=15 => 38, // Acoustic Snare
+        _ => 41       // Low Floor Tom
+    }
+}
+
+/// Picks the noise period for the General MIDI percussion `key`.
+fn noise_drum_period(key: u8) -> u8 {
+    match key {
+        42|44|46|49|51..=53|55|57|59 => 3, // hi-hats and cymbals
+        37..=40 => 10,                     // snares and claps
+        _ => 24
+    }
+}
+
+fn velocity_to_volume(velocity: u8, chan_volume: u8) -> u8 {
+    ((velocity as u32 * chan_volume as u32 * 15 + 127 * 127 / 2) / (127 * 127)) as u8
+}
+
+/// The parsed content of a Standard MIDI File.
+#[derive(Debug, Default)]
+struct Smf {
+    /// Channel events ordered by time.
+    events: Vec<MidiEvent>,
+    /// Tempo changes: tick and microseconds per quarter note.
+    tempos: Vec<(u64, u32)>,
+    /// Ticks per quarter note or, if negative, the negated SMPTE ticks per second.
+    division: i32,
+    title: String,
+    author: String,
+    comments: String,
+}
+
+impl Smf {
+    /// Converts an absolute tick to seconds using the tempo map.
+    fn tick_seconds(&self, tick: u64) -> f64 {
+        if self.division < 0 {
+            return tick as f64 / -self.division as f64
+        }
+        let division = self.division as f64;
+        let mut seconds = 0.0;
+        let mut last_tick = 0;
+        let mut tempo = MICROS_PER_QUARTER;
+        for &(change_tick, change_tempo) in self.tempos.iter().take_while(|&&(t, _)| t < tick) {
+            seconds += (change_tick - last_tick) as f64 * tempo as f64 / (division * 1e6);
+            last_tick = change_tick;
+            tempo = change_tempo;
+        }
+        seconds + (tick - last_tick) as f64 * tempo as f64 / (division * 1e6)
+    }
+}
+
+fn invalid_midi(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads a big-endian chunk header, returning the chunk's tag and data.
+fn read_chunk<'a>(data: &mut &'a [u8]) -> io::Result<(&'a [u8], &'a [u8])> {
+    if data.len() < 8 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely"))
+    }
+    let (tag, rest) = data.split_at(4);
+    let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+    let rest = &rest[4..];
+    if rest.len() < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely"))
+    }
+    let (chunk, rest) = rest.split_at(len);
+    *data = rest;
+    Ok((tag, chunk))
+}
+
+fn read_vlq(data: &mut &[u8]) -> io::Result<u32> {
+    let mut value = 0u32;
+    for _ in 0..4 {
+        let (&byte, rest) = data.split_first().ok_or_else(|| invalid_midi("truncated MIDI track"))?;
+        *data = rest;
+        value = (value << 7) | (byte & 0x7f) as u32;
+        if byte & 0x80 == 0 {
+            return Ok(value)
+        }
+    }
+    Err(invalid_midi("invalid variable-length quantity"))
+}
+
+fn take_bytes<'a>(data: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(invalid_midi("truncated MIDI track"))
+    }
+    let (bytes, rest) = data.split_at(len);
+    *data = rest;
+    Ok(bytes)
+}
+
+fn parse_smf(mut data: &[u8]) -> io::Result<Smf> {
+    let (tag, header) = read_chunk(&mut data)?;
+    if tag != b"MThd" || header.len() < 6 {
+        return Err(invalid_midi("unrecognized file signature"))
+    }
+    let format = u16::from_be_bytes([header[0], header[1]]);
+    let ntracks = u16::from_be_bytes([header[2], header[3]]);
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    if format > 1 {
+        return Err(invalid_midi("unsupported MIDI file format"))
+    }
+    let mut smf = Smf {
+        division: if division & 0x8000 != 0 {
+            let fps = -((division >> 8) as u8 as i8) as i32;
+            -(fps * (division & 0xff) as i32)
+        }
+        else {
+            division as i32
+        },
+        ..Smf::default()
+    };
+    if smf.division == 0 {
+        return Err(invalid_midi("invalid MIDI time division"))
+    }
+    let mut ntrack = 0;
+    while ntrack < ntracks && !data.is_empty() {
+        let (tag, mut track) = read_chunk(&mut data)?;
+        if tag != b"MTrk" {
+            continue
+        }
+        let mut tick = 0u64;
+        let mut running_status = 0u8;
+        while !track.is_empty() {
+            tick += read_vlq(&mut track)? as u64;
+            let mut status = take_bytes(&mut track, 1)?[0];
+            match status {
+                0xff => {
+                    let kind = take_bytes(&mut track, 1)?[0];
+                    let len = read_vlq(&mut track)? as usize;
+                    let meta = take_bytes(&mut track, len)?;
+                    let text = || String::from_utf8_lossy(meta).into_owned();
+                    match kind {
+                        0x01 if smf.comments.is_empty() => smf.comments = text(),
+                        0x02 if smf.author.is_empty() => smf.author = text(),
+                        0x03 if ntrack == 0 && smf.title.is_empty() => smf.title = text(),
+                        0x2f => break,
+                        0x51 if len == 3 => {
+                            let tempo = u32::from_be_bytes([0, meta[0], meta[1], meta[2]]);
+                            smf.tempos.push((tick, tempo));
+                        }
+                        _ => {}
+                    }
+                }
+                0xf0|0xf7 => {
+                    let len = read_vlq(&mut track)? as usize;
+                    take_bytes(&mut track, len)?;
+                }
+                _ => {
+                    let mut first = None;
+                    if status < 0x80 {
+                        if running_status == 0 {
+                            return Err(invalid_midi("invalid MIDI running status"))
+                        }
+                        first = Some(status);
+                        status = running_status;
+                    }
+                    running_status = status;
+                    let len = match status & 0xf0 {
+                        0xc0|0xd0 => 1,
+                        _ => 2
+                    };
+                    let mut event = MidiEvent { tick, status, data: [0;2] };
+                    let mut bytes = first.into_iter().chain(take_bytes(&mut track, len - first.is_some() as usize)?
+                                                           .iter().copied());
+                    event.data[0] = bytes.next().unwrap_or(0);
+                    event.data[1] = bytes.next().unwrap_or(0);
+                    if matches!(status & 0xf0, 0x80|0x90|0xb0) {
+                        smf.events.push(event);
+                    }
+                }
+            }
+        }
+        ntrack += 1;
+    }
+    smf.events.sort_by_key(|event| event.tick);
+    smf.tempos.sort_by_key(|&(tick, _)| tick);
+    Ok(smf)
+}
+
+impl YmSong {
+    /// Converts the song to a type 1 Standard MIDI File.
+    ///
+    /// The file consists of a tempo track, one track for each AY/YM voice channel on MIDI
+    /// channels 0, 1 and 2 and a percussion track on MIDI channel 9.
+    ///
+    /// * One tick equals one frame, and the tempo of 120 BPM is used with the division derived
+    ///   from [YmSong::frame_frequency].
+    /// * Notes are taken from [YmSong::frame_notes] and the velocity from the volume.
+    /// * Gradual pitch changes within `12` semitones are converted to pitch bends, the pitch bend
+    ///   range is being set up with RPN 0. Falling volume within a note is sent as expression
+    ///   (CC 11) changes.
+    /// * Noise is being played on the percussion track with a key depending on the noise period.
+    /// * `DIGI-DRUM` starts are mapped to percussion keys by [DD_SAMPLE_DRUM_KEYS].
+    pub fn to_midi(&self) -> Vec<u8> {
+        let frame_frequency = self.frame_frequency.max(1) as u32;
+        let ticks_per_frame = if frame_frequency.is_multiple_of(2) { 1 } else { 2 };
+        let division = frame_frequency * ticks_per_frame / 2;
+        let end_tick = self.frames.len() as u32 * ticks_per_frame;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(b"MThd");
+        out.extend_from_slice(&6u32.to_be_bytes());
+        out.extend_from_slice(&1u16.to_be_bytes());
+        out.extend_from_slice(&5u16.to_be_bytes());
+        out.extend_from_slice(&(division as u16).to_be_bytes());
+
+        let mut tempo = Track::default();
+        tempo.name(&self.title);
+        if !self.author.is_empty() {
+            tempo.meta(0, 0x02, self.author.as_bytes());
+        }
+        if !self.comments.is_empty() {
+            tempo.meta(0, 0x01, self.comments.as_bytes());
+        }
+        tempo.meta(0, 0x51, &MICROS_PER_QUARTER.to_be_bytes()[1..]);
+        tempo.meta(0, 0x58, &[4, 2, 24, 8]);
+        tempo.finish(end_tick, &mut out);
+
+        let frame_notes = self.frame_notes();
+        for chan in 0..3u8 {
+            let mut track = Track::default();
+            track.name(&format!("Voice {}", (b'A' + chan) as char));
+            track.event(0, &[0xc0 | chan, VOICE_PROGRAM]);
+            for (cc, value) in [(101, 0), (100, 0), (6, BEND_RANGE as u8), (38, 0), (101, 127), (100, 127)] {
+                track.event(0, &[0xb0 | chan, cc, value]);
+            }
+            let mut playing: Option<PlayingNote> = None;
+            for (index, notes) in frame_notes.iter().enumerate() {
+                let tick = index as u32 * ticks_per_frame;
+                let note = notes[chan as usize];
+                match (playing.as_mut(), note) {
+                    (Some(cur), Some(note)) if note.source == cur.source &&
+                                               note.volume <= cur.volume &&
+                                               (exact_pitch(&note) - cur.pitch).abs() < 1.0 &&
+                                               (exact_pitch(&note) - cur.key as f32).abs() <= BEND_RANGE =>
+                    {
+                        let pitch = exact_pitch(&note);
+                        let bend = bend_value(pitch - cur.key as f32);
+                        if bend != cur.bend {
+                            track.event(tick, &[0xe0 | chan, (bend & 0x7f) as u8, (bend >> 7) as u8]);
+                            cur.bend = bend;
+                        }
+                        let expression = (note.volume as u32 * 127 / cur.velocity_volume.max(1) as u32) as u8;
+                        if expression != cur.expression {
+                            track.event(tick, &[0xb0 | chan, 11, expression]);
+                            cur.expression = expression;
+                        }
+                        cur.pitch = pitch;
+                        cur.volume = note.volume;
+                    }
+                    (_, note) => {
+                        if let Some(cur) = playing.take() {
+                            track.event(tick, &[0x80 | chan, cur.key, 0]);
+                        }
+                        if let Some(note) = note {
+                            let pitch = exact_pitch(&note);
+                            let key = note.note;
+                            let bend = bend_value(pitch - key as f32);
+                            track.event(tick, &[0xe0 | chan, (bend & 0x7f) as u8, (bend >> 7) as u8]);
+                            track.event(tick, &[0xb0 | chan, 11, 127]);
+                            track.event(tick, &[0x90 | chan, key, velocity(note.volume)]);
+                            playing = Some(PlayingNote {
+                                key,
+                                source: note.source,
+                                velocity_volume: note.volume,
+                                volume: note.volume,
+                                pitch,
+                                bend,
+                                expression: 127
+                            });
+                        }
+                    }
+                }
+            }
+            if let Some(cur) = playing {
+                track.event(end_tick, &[0x80 | chan, cur.key, 0]);
+            }
+            track.finish(end_tick, &mut out);
+        }
+
+        self.percussion_track(ticks_per_frame, end_tick).finish(end_tick, &mut out);
+        out
+    }
+
+    /// Creates a new `YM6!` song from a Standard MIDI File (format 0 or 1).
+    ///
+    /// Up to 3 monophonic voices are created according to `options`. Notes are quantized to
+    /// frames, converted to tone periods at the requested chipset clock and the velocity,
+    /// scaled by the channel volume (CC 7), is converted to volume. A note that starts and
+    /// ends within a single frame is played for that frame.
+    ///
+    /// The song title, author and comments are taken from the first track name, copyright notice
+    /// and text events.
+    pub fn parse_midi<R: Read>(mut rd: R, options: &MidiImportOptions) -> io::Result<YmSong> {
+        if options.chipset_frequency == 0 || options.frame_frequency == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frequency must not be 0"))
+        }
+        let mut data = Vec::new();
+        rd.read_to_end(&mut data)?;
+        let smf = parse_smf(&data)?;
+
+        let frame_frequency = options.frame_frequency as f64;
+        let mut events: Vec<(u32, MidiEvent)> = smf.events.iter().map(|event| {
+            let frame = (smf.tick_seconds(event.tick) * frame_frequency).round() as u32;
+            (frame, *event)
+        }).collect();
+        events.sort_by_key(|&(frame, _)| frame);
+        let nframes = events.last().map_or(0, |&(frame, _)| frame) as usize + 1;
+
+        let (dd_samples, dd_samples_ends, dd_timer) = match &options.drums {
+            DrumMapping::DigiDrum { samples, sample_rate } => {
+                if samples.len() > MAX_DD_SAMPLES || *sample_rate == 0 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid digi-drum samples"))
+                }
+                let mut dd_samples_ends = [0usize;MAX_DD_SAMPLES];
+                let mut end = 0;
+                for (sample_end, sample) in dd_samples_ends.iter_mut().zip(samples.iter()) {
+                    end += sample.len();
+                    *sample_end = end;
+                }
+                let dd_samples: Box<[u8]> = samples.concat().into();
+                let timer = encode_timer_divisor(MFP_TIMER_FREQUENCY as f64 / *sample_rate as f64);
+                (dd_samples, dd_samples_ends, timer)
+            }
+            _ => (Box::from([]), [0usize;MAX_DD_SAMPLES], (0, 0))
+        };
+
+        let drum_voice = options.drum_voice.min(2) as usize;
+        let mut voices: [Vec<HeldNote>;3] = Default::default();
+        let mut chan_volumes = [127u8;16];
+        let mut noise_burst = (0u8, 0u8);
+        let mut events = events.into_iter().peekable();
+        let mut frames = Vec::with_capacity(nframes);
+        for index in 0..nframes as u32 {
+            let mut frame = YmFrame::default();
+            frame.data[ENV_REG as usize] = 0xff;
+            let mut dd_start = None;
+            while let Some((_, event)) = events.next_if(|&(frame, _)| frame == index) {
+                let chan = event.status & 0x0f;
+                let [key, value] = event.data;
+                let note_on = event.status & 0xf0 == 0x90 && value != 0;
+                let note_off = event.status & 0xf0 == 0x80 || event.status & 0xf0 == 0x90 && value == 0;
+                if event.status & 0xf0 == 0xb0 && key == 7 {
+                    chan_volumes[chan as usize] = value;
+                }
+                else if Some(chan) == options.drum_channel {
+                    if !note_on {
+                        continue
+                    }
+                    match &options.drums {
+                        DrumMapping::Ignore => {}
+                        DrumMapping::Noise => {
+                            noise_burst = (noise_drum_period(key), velocity_to_volume(value, 127));
+                        }
+                        DrumMapping::DigiDrum { samples, .. } => {
+                            dd_start = DD_SAMPLE_DRUM_KEYS.iter().position(|&k| k == key)
+                                                          .filter(|&sample| sample < samples.len())
+                                                          .or(dd_start);
+                        }
+                    }
+                }
+                else if let Some(voice) = options.channel_map[chan as usize] {
+                    let held = &mut voices[voice.min(2) as usize];
+                    if note_on {
+                        held.retain(|note| !(note.chan == chan && note.key == key));
+                        held.push(HeldNote { chan, key, velocity: value, onset: index, released: false });
+                    }
+                    else if note_off {
+                        // notes started in this frame are being played for this frame
+                        held.retain_mut(|note| {
+                            if note.chan != chan || note.key != key {
+                                return true
+                            }
+                            note.released = true;
+                            note.onset == index
+                        });
+                    }
+                }
+            }
+
+            let mut mixer = 0b111111;
+            for (voice, held) in voices.iter_mut().enumerate() {
+                let note = match options.priority {
+                    NotePriority::Last => held.last(),
+                    NotePriority::Highest => held.iter().max_by_key(|note| note.key),
+                    NotePriority::Lowest => held.iter().min_by_key(|note| note.key),
+                };
+                let mut volume = 0;
+                if let Some(note) = note {
+                    let frequency = 440.0 * ((note.key as f64 - 69.0) / 12.0).exp2();
+                    let period = (options.chipset_frequency as f64 / (16.0 * frequency)).round();
+                    frame.set_tone_period(voice as u8, period.clamp(1.0, 0x0fff as f64) as u16);
+                    volume = velocity_to_volume(note.velocity, chan_volumes[note.chan as usize]);
+                    mixer &= !(1 << voice);
+                }
+                held.retain(|note| !note.released);
+                if voice == drum_voice && noise_burst.1 != 0 {
+                    frame.set_noise_period(noise_burst.0);
+                    volume = volume.max(noise_burst.1);
+                    mixer &= !(8 << voice);
+                    noise_burst.1 = noise_burst.1.saturating_sub(3);
+                }
+                frame.data[(VOL_A_REG + voice as u8) as usize] = volume;
+            }
+            frame.data[MIXER_REG as usize] = mixer;
+            if let Some(sample) = dd_start {
+                // DIGI-DRUM on fx1 of YM6!
+                let (prediv3, div8) = dd_timer;
+                let ctrl = FxCtrlFlags::FX_TYPE_DIGI_DRUM.bits() | ((drum_voice as u8 + 1) << 4);
+                frame.data[3] |= ctrl;
+                frame.data[VOL_A_REG as usize] |= prediv3;
+                frame.data[15] = div8;
+                let vol_reg = VOL_A_REG as usize + drum_voice;
+                frame.data[vol_reg] = (frame.data[vol_reg] & 0xe0) | sample as u8;
+            }
+            frames.push(frame);
+        }
+
+        Ok(YmSong::new(YmVersion::Ym6, frames.into_boxed_slice(), 0, smf.title, None)
+                  .with_meta(smf.author, smf.comments)
+                  .with_samples(SongAttributes::DIGIDRUM_4BIT, dd_samples, dd_samples_ends)
+                  .with_frequency(options.chipset_frequency, options.frame_frequency))
+    }
+
+    fn percussion_track(&self, ticks_per_frame: u32, end_tick: u32) -> Track {
+        const CHANNEL: u8 = PERCUSSION_CHANNEL;
+        let mut track = Track::default();
+        track.name("Percussion");
+        let mut noise_volumes = [0u8;3];
+        let mut sounding: Vec<u8> = Vec::new();
+        for (index, frame) in self.frames.iter().enumerate() {
+            let tick = index as u32 * ticks_per_frame;
+            for key in sounding.drain(..) {
+                track.event(tick, &[0x80 | CHANNEL, key, 0]);
+            }
+            let mut keys: Vec<(u8, u8)> = Vec::new();
+            let mixer = frame.data[MIXER_REG as usize];
+            for (chan, last_volume) in noise_volumes.iter_mut().enumerate() {
+                let vol = frame.vol(chan as u8);
+                let volume = if vol & 0x10 != 0 { 15 } else { vol & 0x0f };
+                let volume = if mixer & (8 << chan) == 0 { volume } else { 0 };
+                if volume > *last_volume {
+                    keys.push((noise_drum_key(frame.noise_period()), NOISE_VELOCITY));
+                }
+                *last_volume = volume;
+            }
+            let samples = self.dd_sample_regs(frame).into_iter().flatten()
+                              .map(|reg| frame.data[reg] & 0x1f)
+                              .chain(Some(frame.data[VOL_C_REG as usize])
+                                        .filter(|&vol_c| self.version == YmVersion::Ym2 && vol_c & 0x80 != 0)
+                                        .map(|vol_c| vol_c & 0x7f));
+            for sample in samples {
+                keys.push((DD_SAMPLE_DRUM_KEYS[sample as usize % DD_SAMPLE_DRUM_KEYS.len()], DD_VELOCITY));
+            }
+            for (key, velocity) in keys {
+                if !sounding.contains(&key) {
+                    track.event(tick, &[0x90 | CHANNEL, key, velocity]);
+                    sounding.push(key);
+                }
+            }
+        }
+        for key in sounding.drain(..) {
+            track.event(end_tick, &[0x80 | CHANNEL, key, 0]);
+        }
+        track
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_vlq_works() {
+        for (value, expected) in [(0, &[0x00][..]), (0x7f, &[0x7f]), (0x80, &[0x81, 0x00]),
+                                  (0x2000, &[0xc0, 0x00]), (0x0fffffff, &[0xff, 0xff, 0xff, 0x7f])] {
+            let mut out = Vec::new();
+            write_vlq(&mut out, value);
+            assert_eq!(&out[..], expected);
+        }
+    }
+
+    #[test]
+    fn parse_midi_works() {
+        let frames = [284u16, 284, 190, 0].iter().map(|&period| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(1, period);
+            frame.data[MIXER_REG as usize] = if period == 0 { 0b111111 } else { 0b111101 };
+            frame.data[VOL_B_REG as usize] = 15;
+            frame.data[ENV_REG as usize] = 0xff;
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 0, "Song".into(), None)
+                         .with_meta("Author".into(), String::new());
+        let midi = song.to_midi();
+        let parsed = YmSong::parse_midi(&midi[..], &MidiImportOptions::default()).unwrap();
+        assert_eq!(parsed.version, YmVersion::Ym6);
+        assert_eq!((parsed.title.as_str(), parsed.author.as_str()), ("Song", "Author"));
+        assert_eq!(parsed.frames.len(), 4);
+        let periods: Vec<_> = parsed.frames.iter().map(|f| f.tone_period(1)).collect();
+        assert_eq!(periods[..3], [284, 284, 190]);
+        let volumes: Vec<_> = parsed.frames.iter().map(|f| f.vol(1)).collect();
+        assert_eq!(volumes, [15, 15, 15, 0]);
+        assert_eq!(parsed.frames[0].data[MIXER_REG as usize], 0b111101);
+    }
+
+    #[test]
+    fn to_midi_works() {
+        let frames = [284u16, 284, 0].iter().map(|&period| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(0, period);
+            frame.data[MIXER_REG as usize] = if period == 0 { 0b111111 } else { 0b111110 };
+            frame.data[VOL_A_REG as usize] = 15;
+            frame.data[ENV_REG as usize] = 0xff;
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 0, "Song".into(), None);
+        let midi = song.to_midi();
+        assert_eq!(&midi[..14], b"MThd\0\0\0\x06\0\x01\0\x05\0\x19");
+        assert_eq!(midi.windows(4).filter(|w| w == b"MTrk").count(), 5);
+        assert!(midi.windows(3).any(|w| w == [0x90, 69, 127]));
+        assert!(midi.windows(4).any(|w| w == [2, 0x80, 69, 0]));
+    }
+}
@@ -0,0 +1,235 @@
+//! YM digi-mix songs.
+//!
+//! The `MIX1` files contain a single block of the 8-bit sample data played by the sequence of
+//! [MixBlock]s, each playing a part of the sample data repeatedly at its own replay frequency.
+use core::time::Duration;
+use chrono::NaiveDateTime;
+
+use super::flags::SongAttributes;
+
+/// The fixed point precision of the sample position.
+const POS_PREC: u32 = 12;
+
+/// A single block of the YM digi-mix sequence.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MixBlock {
+    /// The index of the first byte of the block in [YmMixSong::sample_data].
+    pub start: u32,
+    /// The number of bytes of the block.
+    pub length: u32,
+    /// How many times the block is being played.
+    pub repeat: u16,
+    /// The replay frequency in Hz.
+    pub frequency: u16,
+}
+
+impl MixBlock {
+    /// Returns the duration of the block including all of its repetitions.
+    pub fn duration(&self) -> Duration {
+        if self.frequency == 0 {
+            return Duration::ZERO
+        }
+        let seconds = self.length as f64 * self.repeat.max(1) as f64 / self.frequency as f64;
+        Duration::from_secs_f64(seconds)
+    }
+}
+
+/// The **YM digi-mix** music file.
+#[derive(Debug, Clone)]
+pub struct YmMixSong {
+    /// The last modification timestamp of the file from the LHA envelope.
+    pub created: Option<NaiveDateTime>,
+    /// The song attributes.
+    pub song_attrs: SongAttributes,
+    /// The song title.
+    pub title: String,
+    /// The song author.
+    pub author: String,
+    /// The comment.
+    pub comments: String,
+    /// Unsigned 8-bit sample data.
+    pub sample_data: Box<[u8]>,
+    /// The sequence of the played blocks.
+    pub blocks: Box<[MixBlock]>,
+        block: usize,
+        repeats_left: u16,
+        /// The sample position relative to the block start in [POS_PREC] fixed point.
+        pos: u64,
+}
+
+impl YmMixSong {
+    /// Creates a new instance of `YmMixSong` from the given `sample_data` and `blocks`.
+    ///
+    /// # Panics
+    /// Panics if any of the `blocks` doesn't fit in the `sample_data`.
+    pub fn new(
+            sample_data: Box<[u8]>,
+            blocks: Box<[MixBlock]>,
+            title: String,
+            created: Option<NaiveDateTime>
+        ) -> YmMixSong
+    {
+        assert!(blocks.iter().all(|block| block_range_fits(block, sample_data.len())),
+                "mix block out of sample data range");
+        let mut song = YmMixSong {
+            created,
+            song_attrs: SongAttributes::default(),
+            title,
+            author: String::new(),
+            comments: String::new(),
+            sample_data,
+            blocks,
+            block: 0,
+            repeats_left: 0,
+            pos: 0,
+        };
+        song.reset();
+        song
+    }
+
+    /// Returns `YmMixSong` with the `author` and `comments` set from the given arguments.
+    pub fn with_meta(mut self, author: String, comments: String) -> YmMixSong {
+        self.author = author;
+        self.comments = comments;
+        self
+    }
+
+    /// Returns `YmMixSong` with the song attributes set from the given argument.
+    pub fn with_attributes(mut self, song_attrs: SongAttributes) -> YmMixSong {
+        self.song_attrs = song_attrs;
+        self
+    }
+
+    /// Returns the song duration.
+    pub fn song_duration(&self) -> Duration {
+        self.blocks.iter().map(MixBlock::duration).sum()
+    }
+
+    /// Resets the state of the player.
+    pub fn reset(&mut self) {
+        self.block = 0;
+        self.repeats_left = self.blocks.first().map(|block| block.repeat).unwrap_or(0);
+        self.pos = 0;
+    }
+
+    /// Returns the index of the currently played block, which is equal to the number of blocks
+    /// after the song has ended.
+    pub fn cursor(&self) -> usize {
+        self.block
+    }
+
+    /// Renders the song into the mono 16-bit PCM `buffer` at the given `sample_rate`.
+    ///
+    /// The song is being played from the current block. After the last block the song continues
+    /// from the first one if [SongAttributes::LOOP_MODE] is set, otherwise it ends. Blocks that
+    /// are too slow to advance at the `sample_rate`, e.g. with the `0` frequency, are skipped.
+    ///
+    /// Returns the number of samples rendered, which is less than the length of the `buffer`
+    /// if the song has ended. The rest of the `buffer` is filled with silence.
+    pub fn render(&mut self, buffer: &mut [i16], sample_rate: u32) -> usize {
+        buffer.fill(0);
+        if sample_rate == 0 {
+            return 0
+        }
+        let mut rendered = 0;
+        let mut skipped = 0;
+        while rendered < buffer.len() {
+            let Some(block) = self.blocks.get(self.block).copied() else {
+                break
+            };
+            let step = block_step(&block, sample_rate);
+            if step == 0 {
+                skipped += 1;
+                if skipped > self.blocks.len() {
+                    break
+                }
+                self.next_block();
+                continue
+            }
+            skipped = 0;
+            let length = (block.length as u64) << POS_PREC;
+            for out in buffer[rendered..].iter_mut() {
+                if self.pos >= length {
+                    break
+                }
+                let index = block.start as usize + (self.pos >> POS_PREC) as usize;
+                *out = ((self.sample_data[index] as i16) - 128) << 8;
+                self.pos += step;
+                rendered += 1;
+            }
+            if self.pos >= length {
+                self.repeats_left = self.repeats_left.saturating_sub(1);
+                if self.repeats_left == 0 {
+                    self.next_block();
+                }
+                else {
+                    self.pos -= length;
+                }
+            }
+        }
+        rendered
+    }
+
+    /// Advances to the next block, keeping the fractional part of the sample position.
+    fn next_block(&mut self) {
+        self.block += 1;
+        if self.block >= self.blocks.len() && self.song_attrs.is_loop_mode() {
+            self.block = 0;
+        }
+        self.repeats_left = self.blocks.get(self.block).map_or(0, |block| block.repeat);
+        self.pos &= (1 << POS_PREC) - 1;
+    }
+}
+
+fn block_step(block: &MixBlock, sample_rate: u32) -> u64 {
+    ((block.frequency as u64) << POS_PREC) / sample_rate as u64
+}
+
+pub(super) fn block_range_fits(block: &MixBlock, size: usize) -> bool {
+    block.length != 0 && (block.start as u64 + block.length as u64) <= size as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::StSoundSong;
+    use super::*;
+
+    #[test]
+    fn parse_mix_works() {
+        let mut mix = Vec::new();
+        mix.extend_from_slice(b"MIX1LeOnArD!");
+        mix.extend_from_slice(&0u32.to_be_bytes()); // attributes
+        mix.extend_from_slice(&4u32.to_be_bytes()); // sample size
+        mix.extend_from_slice(&2u32.to_be_bytes()); // blocks
+        for (start, length, repeat, freq) in [(0u32, 2u32, 2u16, 100u16), (2, 2, 1, 50)] {
+            mix.extend_from_slice(&start.to_be_bytes());
+            mix.extend_from_slice(&length.to_be_bytes());
+            mix.extend_from_slice(&repeat.to_be_bytes());
+            mix.extend_from_slice(&freq.to_be_bytes());
+        }
+        mix.extend_from_slice(b"Title\0\0Comment\0");
+        mix.extend_from_slice(&[0x80, 0x90, 0xa0, 0xb0]);
+        let StSoundSong::Mix(mut song) = StSoundSong::parse_any(Cursor::new(mix.clone()), "tune").unwrap() else {
+            panic!("not a mix song")
+        };
+        assert_eq!((song.title.as_str(), song.comments.as_str()), ("Title", "Comment"));
+        assert_eq!(&song.sample_data[..], &[0x80, 0x90, 0xa0, 0xb0]);
+        assert_eq!(song.blocks[1], MixBlock { start: 2, length: 2, repeat: 1, frequency: 50 });
+        assert_eq!(song.song_duration(), Duration::from_millis(80));
+        let mut buffer = [0i16;10];
+        assert_eq!(song.render(&mut buffer, 100), 8);
+        assert_eq!(buffer, [0, 0x1000, 0, 0x1000, 0x2000, 0x2000, 0x3000, 0x3000, 0, 0]);
+        assert_eq!(song.cursor(), 2);
+        assert_eq!(song.render(&mut buffer, 100), 0);
+
+        let mut song = song.with_attributes(SongAttributes::LOOP_MODE);
+        song.reset();
+        assert_eq!(song.render(&mut buffer, 100), 10);
+        assert_eq!(buffer, [0, 0x1000, 0, 0x1000, 0x2000, 0x2000, 0x3000, 0x3000, 0, 0x1000]);
+        assert_eq!(song.cursor(), 0);
+
+        mix[34..36].copy_from_slice(&0u16.to_be_bytes());
+        assert!(StSoundSong::parse_any(Cursor::new(mix), "tune").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! MYM (bit-packed register dump) file conversion.
+//!
+//! The file starts with the number of frames as a 16-bit little-endian integer, followed by a bit
+//! stream, most significant bits first. The frames are packed in blocks of 128 frames, the last
+//! block may be shorter. In each block, every register starts with a single bit indicating if it
+//! changes in this block. If it does, each frame of the block holds a bit indicating the change,
+//! followed by the new register value of the register's width.
+//!
+//! The envelope shape register is flagged as changed in every frame it's being written.
+use std::io::{self, Read, Write};
+
+use super::*;
+use super::bits::{BitReader, BitWriter};
+use super::psg::clear_unused_bits;
+
+/// The number of frames in a single block.
+const MYM_BLOCK_FRAMES: usize = 128;
+/// The number of bits of each register.
+const MYM_REG_BITS: [u32;14] = [8, 4, 8, 4, 8, 4, 5, 8, 5, 5, 5, 8, 8, 4];
+
+impl YmSong {
+    /// Attempts to parse a `MYM` file from the given stream source.
+    ///
+    /// Provide `file_name` which will be used as the song title.
+    ///
+    /// The new song is a `YM6!` song without special effects with the default Atari ST chipset
+    /// clock and 50 Hz frame rate, as the format doesn't store them.
+    pub fn parse_mym<R, S>(mut rd: R, file_name: S) -> io::Result<YmSong>
+        where R: Read, S: Into<String>
+    {
+        let mut data = Vec::new();
+        rd.read_to_end(&mut data)?;
+        if data.len() < 2 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely"))
+        }
+        let nframes = u16::from_le_bytes([data[0], data[1]]) as usize;
+        if nframes == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no frames"))
+        }
+        let mut bits = BitReader::new(&data[2..]);
+        let mut regs = [0u8;14];
+        let mut frames = vec![YmFrame::default();nframes];
+        for block in frames.chunks_mut(MYM_BLOCK_FRAMES) {
+            for (reg, (val, &nbits)) in regs.iter_mut().zip(MYM_REG_BITS.iter()).enumerate() {
+                let changes = bits.bit();
+                for frame in block.iter_mut() {
+                    let mut changed = false;
+                    if changes && bits.bit() {
+                        *val = bits.bits(nbits) as u8;
+                        changed = true;
+                    }
+                    frame.data[reg] = match reg as u8 {
+                        ENV_REG if !changed => 0xff,
+                        _ => *val
+                    };
+                }
+            }
+        }
+        if bits.is_overrun() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely"))
+        }
+        for frame in frames.iter_mut() {
+            clear_unused_bits(&mut frame.data);
+        }
+        Ok(YmSong::new(YmVersion::Ym6, frames.into_boxed_slice(), 0, file_name.into(), None))
+    }
+
+    /// Writes the song as a `MYM` file to the given stream.
+    ///
+    /// Only the register data is stored in this format. The YM-specific special effects are
+    /// dropped, the same way as in [YmSong::write_psg].
+    ///
+    /// Returns an error if the song has more than `65535` frames.
+    pub fn write_mym<W: Write>(&self, mut wr: W) -> io::Result<()> {
+        let nframes = u16::try_from(self.frames.len())
+                      .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too many frames"))?;
+        let dump = self.ay_register_dump();
+        let mut bits = BitWriter::default();
+        let mut last = [0u8;14];
+        for block in dump.chunks(MYM_BLOCK_FRAMES) {
+            for (reg, (last, &nbits)) in last.iter_mut().zip(MYM_REG_BITS.iter()).enumerate() {
+                let is_change = |val: u8, last: u8| match reg as u8 {
+                    ENV_REG => val != 0xff,
+                    _ => val != last
+                };
+                let mut prev = *last;
+                let changes = block.iter().any(|regs| {
+                    let changed = is_change(regs[reg], prev);
+                    prev = regs[reg];
+                    changed
+                });
+                bits.put(1, changes as u32);
+                if !changes {
+                    continue
+                }
+                for regs in block {
+                    let val = regs[reg];
+                    if is_change(val, *last) {
+                        bits.put(1, 1);
+                        bits.put(nbits, val as u32);
+                        *last = val;
+                    }
+                    else {
+                        bits.put(1, 0);
+                    }
+                }
+            }
+        }
+        wr.write_all(&nframes.to_le_bytes())?;
+        wr.write_all(&bits.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mym_roundtrip_works() {
+        let frames = (0..300u32).map(|n| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(2, (n / 7) as u16);
+            frame.data[MIXER_REG as usize] = 0b111011;
+            frame.data[VOL_C_REG as usize] = if n < 200 { 12 } else { 0x10 };
+            frame.data[ENV_REG as usize] = if n == 200 { 0x0c } else { 0xff };
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 0, String::new(), None);
+        let mut mym = Vec::new();
+        song.write_mym(&mut mym).unwrap();
+        assert_eq!(&mym[..2], &[44, 1]);
+        assert!(mym.len() < 300);
+        let parsed = YmSong::parse_mym(&mym[..], "tune").unwrap();
+        assert_eq!(parsed.title, "tune");
+        assert_eq!(parsed.frames.len(), 300);
+        for (a, b) in parsed.frames.iter().zip(song.frames.iter()) {
+            assert_eq!(a.data, b.data);
+        }
+        assert!(YmSong::parse_mym(&mym[..mym.len() / 2], "tune").is_err());
+    }
+}
@@ -0,0 +1,240 @@
+//! Musical note analysis.
+use super::*;
+
+/// What determines the pitch of a voice channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PitchSource {
+    /// The tone generator period.
+    Tone,
+    /// The period of a repeating envelope shape, while the tone is disabled ("buzzer" sound).
+    Envelope,
+    /// The `SID voice` effect frequency.
+    SidVoice,
+    /// The `Sinus SID` effect frequency.
+    SinusSid,
+    /// The `Sync Buzzer` effect frequency.
+    SyncBuzzer,
+}
+
+/// The pitch of a single voice channel in a single frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameNote {
+    /// The frequency in Hz.
+    pub frequency: f32,
+    /// The nearest MIDI note number, where 69 is A4 (440 Hz).
+    pub note: u8,
+    /// The deviation of the `frequency` from the MIDI `note` in cents `[-50.0, 50.0]`.
+    pub cents: f32,
+    /// What determines the pitch.
+    pub source: PitchSource,
+    /// The channel's volume `[0, 15]`, the envelope controlled volume is reported as `15`.
+    pub volume: u8,
+}
+
+/// A note played on a single voice channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteEvent {
+    /// The index of the first frame of the note.
+    pub onset: u32,
+    /// The index of the frame after the last frame of the note.
+    pub offset: u32,
+    /// The pitch at the onset of the note.
+    pub start: FrameNote,
+}
+
+impl FrameNote {
+    /// Creates a new `FrameNote` from the given `frequency`.
+    ///
+    /// Returns `None` if the frequency is out of the MIDI notes range.
+    pub fn from_frequency(frequency: f32, source: PitchSource, volume: u8) -> Option<FrameNote> {
+        if frequency.is_nan() || frequency <= 0.0 {
+            return None
+        }
+        let exact = 69.0 + 12.0 * (frequency / 440.0).log2();
+        let note = exact.round();
+        if !(0.0..=127.0).contains(&note) {
+            return None
+        }
+        let cents = (exact - note) * 100.0;
+        Some(FrameNote { frequency, note: note as u8, cents, source, volume })
+    }
+}
+
+/// Returns `true` if the envelope `shape` repeats continuously.
+fn is_repeating_shape(shape: u8) -> bool {
+    matches!(shape & 0x0f, 8|10|12|14)
+}
+
+impl YmSong {
+    /// Returns the frequency in Hz of the tone generator for the given tone `period`.
+    pub fn tone_frequency(&self, period: u16) -> f32 {
+        self.clock_frequency() / (16.0 * period.max(1) as f32)
+    }
+
+    /// Returns the frequency in Hz of a single cycle of the repeating envelope `shape` for
+    /// the given envelope `period`.
+    pub fn envelope_frequency(&self, period: u16, shape: u8) -> f32 {
+        let cycle = match shape & 0x0f {
+            10|14 => 512.0,
+            _ => 256.0
+        };
+        self.clock_frequency() / (cycle * period.max(1) as f32)
+    }
+
+    /// Determines the pitch of each voice channel in every frame.
+    ///
+    /// The pitch is taken from:
+    ///
+    /// * an active `SID voice` or `Sinus SID` effect on the channel,
+    /// * an active `Sync Buzzer` effect if the channel's volume is controlled by the envelope,
+    /// * the envelope period if the channel's volume is controlled by the repeating envelope
+    ///   and the tone is disabled,
+    /// * the tone period if the tone is enabled and the volume is not `0`.
+    ///
+    /// Otherwise, e.g. when a channel plays only noise, a `DIGI-DRUM` sample or is silent,
+    /// no note is reported.
+    pub fn frame_notes(&self) -> Vec<[Option<FrameNote>;3]> {
+        let mut shape = 0;
+        self.frames.iter().map(|frame| {
+            if frame.data[ENV_REG as usize] != 0xff {
+                shape = match self.version {
+                    // the player always writes 0x10 in this format
+                    YmVersion::Ym2 => 0,
+                    _ => frame.data[ENV_REG as usize] & 0x0f
+                };
+            }
+            let env_period = match self.version {
+                YmVersion::Ym2 => frame.data[ENV_PER_FINE_REG as usize].into(),
+                _ => frame.env_period()
+            };
+            // a channel taken by an effect, `Some(None)` for a `DIGI-DRUM`
+            let mut fx_notes: [Option<Option<FrameNote>>;3] = [None;3];
+            let mut buzzer = None;
+            for (fx, chan, divisor) in self.frame_effects(frame).into_iter().flatten() {
+                let rate = MFP_TIMER_FREQUENCY as f32 / divisor.get() as f32;
+                let vol = frame.data[(VOL_A_REG + chan) as usize] & 0x0f;
+                let fx_note = &mut fx_notes[chan as usize];
+                match fx {
+                    FxType::SidVoice => {
+                        *fx_note = Some(FrameNote::from_frequency(rate / 2.0, PitchSource::SidVoice, vol));
+                    }
+                    FxType::SinusSid => {
+                        *fx_note = Some(FrameNote::from_frequency(rate / 8.0, PitchSource::SinusSid, vol));
+                    }
+                    FxType::SyncBuzz => buzzer = Some(rate),
+                    FxType::DigiDrum => *fx_note = Some(None)
+                }
+            }
+            if self.version == YmVersion::Ym2 && frame.data[VOL_C_REG as usize] & 0x80 != 0 {
+                fx_notes[2] = Some(None);
+            }
+            let mixer = frame.data[MIXER_REG as usize];
+            let mut notes = [None;3];
+            for (chan, note) in notes.iter_mut().enumerate() {
+                let vol = frame.vol(chan as u8);
+                let env_mode = vol & 0x10 != 0;
+                let volume = if env_mode { 15 } else { vol & 0x0f };
+                let tone_on = mixer & (1 << chan) == 0;
+                *note = match (fx_notes[chan], buzzer) {
+                    (Some(fx_note), _) => fx_note,
+                    (None, Some(frequency)) if env_mode => {
+                        FrameNote::from_frequency(frequency, PitchSource::SyncBuzzer, volume)
+                    }
+                    _ if env_mode && !tone_on && is_repeating_shape(shape) => {
+                        let frequency = self.envelope_frequency(env_period, shape);
+                        FrameNote::from_frequency(frequency, PitchSource::Envelope, volume)
+                    }
+                    _ if tone_on && volume != 0 => {
+                        let frequency = self.tone_frequency(frame.tone_period(chan as u8));
+                        FrameNote::from_frequency(frequency, PitchSource::Tone, volume)
+                    }
+                    _ => None
+                };
+            }
+            notes
+        }).collect()
+    }
+
+    /// Creates the list of note events for each voice channel.
+    ///
+    /// A new note begins when the MIDI note number or the pitch source changes, or when the
+    /// volume rises. A note ends when a new one begins or when the channel goes silent.
+    pub fn note_events(&self) -> [Vec<NoteEvent>;3] {
+        let mut events: [Vec<NoteEvent>;3] = Default::default();
+        let mut last: [Option<FrameNote>;3] = [None;3];
+        let nframes = self.frames.len() as u32;
+        for (index, notes) in self.frame_notes().into_iter().enumerate() {
+            let index = index as u32;
+            for ((note, prev), events) in notes.into_iter().zip(last.iter_mut()).zip(events.iter_mut()) {
+                let continues = match (note, *prev) {
+                    (Some(note), Some(prev)) => note.note == prev.note &&
+                                                note.source == prev.source &&
+                                                note.volume <= prev.volume,
+                    _ => false
+                };
+                if !continues {
+                    if prev.is_some() {
+                        events.last_mut().unwrap().offset = index;
+                    }
+                    if let Some(note) = note {
+                        events.push(NoteEvent { onset: index, offset: nframes, start: note });
+                    }
+                }
+                *prev = note;
+            }
+        }
+        events
+    }
+
+    /// Returns the special effects started in the `frame` with their channel and timer divisor.
+    pub(super) fn frame_effects(&self, frame: &YmFrame) -> [Option<(FxType, u8, NonZeroU32)>;2] {
+        match self.version {
+            YmVersion::Ym2|YmVersion::Ym3 => [None, None],
+            YmVersion::Ym4|YmVersion::Ym5 => [
+                frame.fx0().ts_channel().and_then(|(_, chan)|
+                    frame.timer_divisor0().map(|div| (FxType::SidVoice, chan, div))
+                ),
+                frame.fx1().dd_channel().and_then(|chan|
+                    frame.timer_divisor1().map(|div| (FxType::DigiDrum, chan, div))
+                )
+            ],
+            YmVersion::Ym6 => [
+                frame.fx0().fx6_channel().and_then(|(fx, chan)|
+                    frame.timer_divisor0().map(|div| (fx, chan, div))
+                ),
+                frame.fx1().fx6_channel().and_then(|(fx, chan)|
+                    frame.timer_divisor1().map(|div| (fx, chan, div))
+                )
+            ]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_events_work() {
+        // A4, A4 quieter, A4 louder, silence, buzzer
+        let frames = [(284, 15), (284, 12), (284, 14), (0, 0), (0, 0x10)].iter().map(|&(period, vol)| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(0, period);
+            frame.data[MIXER_REG as usize] = if period == 0 { 0b111111 } else { 0b111110 };
+            frame.data[VOL_A_REG as usize] = vol;
+            frame.set_env_period(72);
+            frame.data[ENV_REG as usize] = 0x08;
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 0, String::new(), None);
+        let note = FrameNote::from_frequency(440.0, PitchSource::Tone, 15).unwrap();
+        assert_eq!((note.note, note.cents), (69, 0.0));
+        let [a, b, c] = song.note_events();
+        assert!(b.is_empty() && c.is_empty());
+        let summary: Vec<_> = a.iter().map(|e| (e.onset, e.offset, e.start.note, e.start.source)).collect();
+        assert_eq!(summary, [(0, 2, 69, PitchSource::Tone),
+                             (2, 3, 69, PitchSource::Tone),
+                             (4, 5, 45, PitchSource::Envelope)]);
+        assert!(a[0].start.cents.abs() < 1.0);
+    }
+}
@@ -1,12 +1,14 @@
 use core::convert::TryInto;
 use core::mem;
-use std::io::{self, Read, Seek, SeekFrom};
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
 
 use log::warn;
 
 use delharc::*;
 
 use super::*;
+use super::tracker::*;
+use super::mix::*;
 
 const YM2_SAMPLES_4BIT: &[u8] = include_bytes!("../../resources/ym2_samples4bit.bin");
 
@@ -24,25 +26,15 @@ impl YmSong {
     ///
     /// Provide `file_name` which will be used as a fallback song title.
     ///
-    /// Returns an instance of `YmSong` on success.
+    /// Returns an instance of `YmSong` on success. The sample based `YMT1`, `YMT2` and `MIX1`
+    /// files are not AY/YM register songs and can only be parsed with [StSoundSong::parse_any].
     pub fn parse_any<R, S>(
-            mut rd: R,
+            rd: R,
             file_name: S
         ) -> io::Result<YmSong>
         where R: Read + Seek, S: Into<String>
     {
-        let pos = rd.seek(SeekFrom::Current(0))?;
-        let mut rd = match LhaDecodeReader::new(rd) {
-            Ok(lha) if lha.is_decoder_supported() => {
-                return Self::parse_lha_reader(lha)
-            }
-            Ok(lha) => lha.into_inner(),
-            Err(e) => e.into_inner()
-        };
-        let file_len = rd.seek(SeekFrom::End(0))?;
-        rd.seek(SeekFrom::Start(pos))?;
-        let mut buf_rd = io::BufReader::new(rd);
-        parse_ym(&mut buf_rd, file_len, file_name.into(), None)
+        parse_any_with(rd, file_name.into(), parse_ym)
     }
 
     /// Attempts to parse an uncompressed YM-file from the given stream source.
@@ -71,20 +63,74 @@ impl YmSong {
     }
 
     fn parse_lha_reader<R: Read>(lha_reader: LhaDecodeReader<R>) -> io::Result<YmSong> {
-        // let header = lha_reader.header();
-        // println!("{:?} {} {:?} {} {:?}",
-        //     header.parse_pathname(),
-        //     header.level,
-        //     header.compression_method().unwrap(),
-        //     header.parse_last_modified(),
-        //     header.parse_os_type().unwrap());
-        let title = lha_reader.header().parse_pathname().file_name()
-                        .map(|s| s.to_string_lossy().into_owned())
-                        .unwrap_or_else(|| String::new());
-        let created = lha_reader.header().parse_last_modified().to_naive_utc();
-        let file_len = lha_reader.len();
-        let mut buf_rd = io::BufReader::new(lha_reader);
-        parse_ym(&mut buf_rd, file_len, title, created)
+        parse_lha_reader_with(lha_reader, parse_ym)
+    }
+}
+
+impl StSoundSong {
+    /// Attempts to parse any of the StSound music files that can be either compressed or
+    /// uncompressed, from the given stream source.
+    ///
+    /// Provide `file_name` which will be used as a fallback song title.
+    ///
+    /// Returns an instance of `StSoundSong` on success.
+    pub fn parse_any<R, S>(
+            rd: R,
+            file_name: S
+        ) -> io::Result<StSoundSong>
+        where R: Read + Seek, S: Into<String>
+    {
+        parse_any_with(rd, file_name.into(), parse_stsound)
+    }
+}
+
+type ParseFn<T> = fn(&mut dyn io::BufRead, u64, String, Option<NaiveDateTime>) -> io::Result<T>;
+
+fn parse_any_with<R: Read + Seek, T>(mut rd: R, file_name: String, parse: ParseFn<T>) -> io::Result<T> {
+    let pos = rd.seek(SeekFrom::Current(0))?;
+    let mut rd = match LhaDecodeReader::new(rd) {
+        Ok(lha) if lha.is_decoder_supported() => {
+            return parse_lha_reader_with(lha, parse)
+        }
+        Ok(lha) => lha.into_inner(),
+        Err(e) => e.into_inner()
+    };
+    let file_len = rd.seek(SeekFrom::End(0))?;
+    rd.seek(SeekFrom::Start(pos))?;
+    let mut buf_rd = io::BufReader::new(rd);
+    if ice::is_ice_packed(buf_rd.fill_buf()?) {
+        let mut packed = Vec::new();
+        buf_rd.read_to_end(&mut packed)?;
+        let data = ice::depack(&packed)?;
+        return parse(&mut &data[..], data.len() as u64, file_name, None)
+    }
+    parse(&mut buf_rd, file_len, file_name, None)
+}
+
+fn parse_lha_reader_with<R: Read, T>(lha_reader: LhaDecodeReader<R>, parse: ParseFn<T>) -> io::Result<T> {
+    let title = lha_reader.header().parse_pathname().file_name()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| String::new());
+    let created = lha_reader.header().parse_last_modified().to_naive_utc();
+    let file_len = lha_reader.len();
+    let mut buf_rd = io::BufReader::new(lha_reader);
+    parse(&mut buf_rd, file_len, title, created)
+}
+
+fn parse_stsound(
+        rd: &mut dyn io::BufRead,
+        file_len: u64,
+        title: String,
+        created: Option<NaiveDateTime>
+    ) -> io::Result<StSoundSong>
+{
+    let mut ident = [0u8;4];
+    rd.read_exact(&mut ident)?;
+    match &ident {
+        b"YMT1" => parse_ymt(YmTrackerVersion::Ymt1, rd, created).map(StSoundSong::Tracker),
+        b"YMT2" => parse_ymt(YmTrackerVersion::Ymt2, rd, created).map(StSoundSong::Tracker),
+        b"MIX1" => parse_mix(rd, created).map(StSoundSong::Mix),
+        _ => parse_ym_ident(ident, rd, file_len, title, created).map(StSoundSong::Ym)
     }
 }
 
@@ -97,6 +143,22 @@ fn parse_ym(
 {
     let mut ident = [0u8;4];
     rd.read_exact(&mut ident)?;
+    parse_ym_ident(ident, rd, file_len, title, created)
+}
+
+/// Returns `true` if `ident` is one of the YM-file signatures.
+pub(super) fn is_ym_ident(ident: &[u8;4]) -> bool {
+    matches!(ident, b"YM2!"|b"YM3!"|b"YM3b"|b"YM4!"|b"YM5!"|b"YM6!")
+}
+
+pub(super) fn parse_ym_ident(
+        ident: [u8;4],
+        rd: &mut dyn io::BufRead,
+        file_len: u64,
+        title: String,
+        created: Option<NaiveDateTime>
+    ) -> io::Result<YmSong>
+{
     match &ident {
         b"YM2!" => parse_ym2(rd, file_len - mem::size_of_val(&ident) as u64, title, created),
         b"YM3!"|
@@ -104,6 +166,10 @@ fn parse_ym(
         b"YM4!" => parse_ym4(rd, created),
         b"YM5!" => parse_ym5(YmVersion::Ym5, rd, created),
         b"YM6!" => parse_ym5(YmVersion::Ym6, rd, created),
+        b"YMT1"|b"YMT2"|b"MIX1" => {
+            Err(io::Error::new(io::ErrorKind::InvalidData,
+                "sample based StSound song, use StSoundSong::parse_any to parse it"))
+        }
         _ => {
             Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognized file signature"))
         }
@@ -211,11 +277,7 @@ fn parse_ym5<R: io::BufRead>(
 }
 
 fn parse_ym4_common<R: Read>(mut rd: R) -> io::Result<(usize, SongAttributes, u16)> {
-    let mut leonard = [0u8;8];
-    rd.read_exact(&mut leonard)?;
-    if &leonard != b"LeOnArD!" {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognized file verify signature"))
-    }
+    read_leonard_tag(rd.by_ref())?;
     let nframes = read_dword(rd.by_ref())?
                   .try_into().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
     if nframes == 0 {
@@ -229,6 +291,128 @@ fn parse_ym4_common<R: Read>(mut rd: R) -> io::Result<(usize, SongAttributes, u1
     Ok((nframes, attrs, dd_nsamples))
 }
 
+fn parse_ymt<R: io::BufRead>(
+        version: YmTrackerVersion,
+        mut rd: R,
+        created: Option<NaiveDateTime>
+    ) -> io::Result<YmTrackerSong>
+{
+    read_leonard_tag(rd.by_ref())?;
+    let nvoices = read_word(rd.by_ref())?;
+    if nvoices == 0 || nvoices as usize > MAX_TRACKER_VOICES {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid number of voices"))
+    }
+    let frame_frequency = read_word(rd.by_ref())?;
+    if frame_frequency == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame period must not be 0"))
+    }
+    let nframes: usize = read_dword(rd.by_ref())?
+                         .try_into().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if nframes == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no YM data"))
+    }
+    let loop_frame = read_dword(rd.by_ref())?;
+    let nsamples = read_word(rd.by_ref())?;
+    let mut attrs = read_dword(rd.by_ref())?;
+    let mut freq_shift = 0;
+    if version == YmTrackerVersion::Ymt2 {
+        freq_shift = (attrs >> 28) as u8;
+        attrs &= 0x0fff_ffff;
+    }
+    let song_attrs = SongAttributes::from_bits_truncate(attrs);
+    let (title, author, comments) = read_song_meta(rd.by_ref())?;
+
+    let mut samples = Vec::with_capacity(nsamples as usize);
+    for _ in 0..nsamples {
+        let size = read_word(rd.by_ref())? as usize;
+        let mut repeat_len = size;
+        if version == YmTrackerVersion::Ymt2 {
+            repeat_len = (read_word(rd.by_ref())? as usize).min(size);
+            let _flags = read_word(rd.by_ref())?;
+        }
+        let mut data = vec![0u8;size];
+        rd.read_exact(&mut data)?;
+        if song_attrs.is_signed() {
+            for t in data.iter_mut() {
+                *t ^= 0x80;
+            }
+        }
+        samples.push(TrackerSample { data: data.into_boxed_slice(), repeat_len });
+    }
+
+    let nbytes = 4 * nvoices as usize;
+    let mut data = vec![0u8;nframes * nbytes];
+    rd.read_exact(&mut data)?;
+    if song_attrs.is_interleaved() {
+        let stream = mem::take(&mut data);
+        data.resize(stream.len(), 0);
+        for (column, bytes) in stream.chunks_exact(nframes).enumerate() {
+            for (row, byte) in bytes.iter().enumerate() {
+                data[row * nbytes + column] = *byte;
+            }
+        }
+    }
+    let lines = data.chunks_exact(4).map(|line| TrackerLine {
+        note: line[0],
+        volume: line[1],
+        freq: u16::from_be_bytes([line[2], line[3]])
+    }).collect();
+
+    Ok(YmTrackerSong::new(version, nvoices as u8, lines, loop_frame, title, created)
+                     .with_samples(song_attrs, samples, freq_shift)
+                     .with_meta(author, comments)
+                     .with_frame_frequency(frame_frequency))
+}
+
+fn parse_mix<R: io::BufRead>(mut rd: R, created: Option<NaiveDateTime>) -> io::Result<YmMixSong> {
+    read_leonard_tag(rd.by_ref())?;
+    let song_attrs = SongAttributes::from_bits_truncate(read_dword(rd.by_ref())?);
+    let sample_size: usize = read_dword(rd.by_ref())?
+                             .try_into().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let nblocks = read_dword(rd.by_ref())?;
+    if nblocks == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no YM data"))
+    }
+    let mut blocks = Vec::new();
+    for _ in 0..nblocks {
+        let block = MixBlock {
+            start: read_dword(rd.by_ref())?,
+            length: read_dword(rd.by_ref())?,
+            repeat: read_word(rd.by_ref())?,
+            frequency: read_word(rd.by_ref())?
+        };
+        if !block_range_fits(&block, sample_size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "mix block out of sample data range"))
+        }
+        if block.frequency == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "mix block frequency is 0"))
+        }
+        blocks.push(block);
+    }
+    let (title, author, comments) = read_song_meta(rd.by_ref())?;
+    let mut sample_data = Vec::new();
+    if sample_size != rd.by_ref().take(sample_size as u64).read_to_end(&mut sample_data)? {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely"))
+    }
+    if song_attrs.is_signed() {
+        for t in sample_data.iter_mut() {
+            *t ^= 0x80;
+        }
+    }
+    Ok(YmMixSong::new(sample_data.into_boxed_slice(), blocks.into_boxed_slice(), title, created)
+                 .with_attributes(song_attrs)
+                 .with_meta(author, comments))
+}
+
+fn read_leonard_tag<R: Read>(mut rd: R) -> io::Result<()> {
+    let mut leonard = [0u8;8];
+    rd.read_exact(&mut leonard)?;
+    if &leonard != b"LeOnArD!" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognized file verify signature"))
+    }
+    Ok(())
+}
+
 fn read_digidrum_samples<R: Read>(
         mut rd: R,
         nsamples: u16,
@@ -3,8 +3,99 @@ use super::*;
 
 use super::parse::YM2_SAMPLE_ENDS;
 
+/// The borrowed song data needed to play the song.
+pub(super) struct SongData<'a> {
+    pub version: YmVersion,
+    pub chipset_frequency: u32,
+    pub frame_frequency: u16,
+    pub loop_frame: u32,
+    pub frames: &'a [YmFrame],
+    pub dd_samples: &'a [u8],
+    pub dd_samples_ends: &'a [usize;MAX_DD_SAMPLES],
+}
+
+/// The state of the player.
+#[derive(Debug, Default, Clone)]
+pub(super) struct PlayerState {
+    cursor: usize,
+    voice_effects: [(SidVoice, SinusSid, DigiDrum); 3],
+    buzzer: SyncBuzzer,
+}
+
+impl SongData<'_> {
+    #[inline]
+    pub fn clock_frequency(&self) -> f32 {
+        self.chipset_frequency as f32
+    }
+
+    pub fn frame_cycles(&self) -> f32 {
+        self.clock_frequency() / self.frame_frequency as f32
+    }
+
+    pub fn timer_interval(&self, divisor: NonZeroU32) -> f32 {
+        let divisor = divisor.get() as f32;
+        self.clock_frequency() * divisor / MFP_TIMER_FREQUENCY as f32
+    }
+
+    pub fn sample_data_range(&self, sample: usize) -> Range<usize> {
+        let end = self.dd_samples_ends[sample];
+        let start = match sample {
+            0 => 0,
+            index => self.dd_samples_ends[index - 1]
+        };
+        start..end
+    }
+}
+
 impl YmSong {
     /// Resets the state of the player.
+    pub fn reset(&mut self) {
+        self.player.reset();
+    }
+
+    /// Returns the current frame cursor value.
+    pub fn cursor(&self) -> u32 {
+        self.player.cursor()
+    }
+
+    pub(super) fn song_data(&self) -> SongData<'_> {
+        SongData {
+            version: self.version,
+            chipset_frequency: self.chipset_frequency,
+            frame_frequency: self.frame_frequency,
+            loop_frame: self.loop_frame,
+            frames: &self.frames,
+            dd_samples: &self.dd_samples,
+            dd_samples_ends: &self.dd_samples_ends
+        }
+    }
+
+    /// Produces the changes to the AY/YM chipset registers for the current frame indicated by
+    /// the cursor and advances the cursor forward one frame.
+    ///
+    /// Provide a function that receives 3 arguments:
+    /// * The timestamp as a cycle relative to the current frame, where `0.0` is the
+    ///   beginning of a frame. The timestamp will be always larger than `0.0` and less than the
+    ///   value returned from [YmSong::frame_cycles].
+    /// * The modified register's number `[0, 13]`.
+    /// * The modified register's new value.
+    ///
+    /// The changes are always being provided in the ascending order of the timestamp.
+    ///
+    /// Returns `true` if this was the last frame before the cursor has been set to the loop frame.
+    /// Otherwise returns `false`.
+    ///
+    /// This method can be used to populate changes to the AY/YM chipset or an emulator, to play
+    /// the YM-file song.
+    pub fn produce_next_ay_frame<F: FnMut(f32, u8, u8)>(&mut self, rec: F) -> bool {
+        let mut player = core::mem::take(&mut self.player);
+        let looped = player.produce_next_ay_frame(&self.song_data(), rec);
+        self.player = player;
+        looped
+    }
+}
+
+impl PlayerState {
     pub fn reset(&mut self) {
         self.cursor = 0;
         for (sv, ss, dd) in self.voice_effects.iter_mut() {
@@ -15,13 +106,12 @@ impl YmSong {
         self.buzzer.stop();
     }
 
-    /// Returns the current frame cursor value.
     pub fn cursor(&self) -> u32 {
         self.cursor as u32
     }
 
-    fn fx_update(&mut self, fx: FxType, chan: u8, divisor: NonZeroU32, vol: u8) {
-        let step = self.timer_interval(divisor);
+    fn fx_update(&mut self, song: &SongData, fx: FxType, chan: u8, divisor: NonZeroU32, vol: u8) {
+        let step = song.timer_interval(divisor);
         match fx {
             FxType::SidVoice => {
                 // println!("SID voice on {} v: {} {} Hz", chan, vol & 0x0f, self.clock_frequency() as f32 / step);
@@ -30,7 +120,7 @@ impl YmSong {
             }
             FxType::DigiDrum => {
                 // println!("digi on {} sample: {} {} Hz", chan, sample, self.clock_frequency() as f32 / step);
-                let Range { start, end } = self.sample_data_range(vol as usize);
+                let Range { start, end } = song.sample_data_range(vol as usize);
                 let ddrum = &mut self.voice_effects[chan as usize].2;
                 ddrum.start(start, end, step);
             }
@@ -46,8 +136,8 @@ impl YmSong {
         }
     }
 
-    fn play_ym2_frame<F: FnMut(f32, u8, u8)>(&mut self, rec: &mut F) {
-        let frame = &self.frames[self.cursor];
+    fn play_ym2_frame<F: FnMut(f32, u8, u8)>(&mut self, song: &SongData, rec: &mut F) {
+        let frame = &song.frames[self.cursor];
         let shape = frame.data[ENV_REG as usize];
         if shape != 0xff {
             rec(0.0, ENV_PER_FINE_REG, frame.data[ENV_PER_FINE_REG as usize]);
@@ -61,7 +151,7 @@ impl YmSong {
             let prediv: u32 = frame.data[ENV_PER_COARSE_REG as usize].into();
             if let Some(&end) = YM2_SAMPLE_ENDS.get(sample) {
                 if let Some(divisor) = NonZeroU32::new(4 * prediv) {
-                    let step = self.timer_interval(divisor);
+                    let step = song.timer_interval(divisor);
                     // println!("MADMAX digi sample: {} div: {} {} Hz", sample, divisor, self.clock_frequency() as f32 / step);
                     let cur = match sample {
                         0 => 0,
@@ -74,8 +164,8 @@ impl YmSong {
         }
     }
 
-    fn play_ym3_frame<F: FnMut(f32, u8, u8)>(&mut self, rec: &mut F) {
-        let frame = &self.frames[self.cursor];
+    fn play_ym3_frame<F: FnMut(f32, u8, u8)>(&mut self, song: &SongData, rec: &mut F) {
+        let frame = &song.frames[self.cursor];
         for (val, reg) in frame.data[ENV_PER_FINE_REG as usize..].iter().copied().zip(ENV_PER_FINE_REG..ENV_REG) {
             rec(0.0, reg, val);
         }
@@ -85,9 +175,9 @@ impl YmSong {
         }
     }
 
-    fn play_ym5_frame<F: FnMut(f32, u8, u8)>(&mut self, rec: &mut F) {
-        self.play_ym3_frame(rec);
-        let frame = &self.frames[self.cursor];
+    fn play_ym5_frame<F: FnMut(f32, u8, u8)>(&mut self, song: &SongData, rec: &mut F) {
+        self.play_ym3_frame(song, rec);
+        let frame = &song.frames[self.cursor];
         let ts = frame.fx0().ts_channel().and_then(|(reset_sid, chan)|
             frame.timer_divisor0().map(|div| (reset_sid, chan, div, frame.vol(chan)))
         );
@@ -98,16 +188,16 @@ impl YmSong {
             if reset_sid {
                 self.voice_effects[chan as usize].0.reset();
             }
-            self.fx_update(FxType::SidVoice, chan, divisor, vol);
+            self.fx_update(song, FxType::SidVoice, chan, divisor, vol);
         }
         if let Some((chan, divisor, vol)) = dd {
-            self.fx_update(FxType::DigiDrum, chan, divisor, vol);
+            self.fx_update(song, FxType::DigiDrum, chan, divisor, vol);
         }        
     }
 
-    fn play_ym6_frame<F: FnMut(f32, u8, u8)>(&mut self, rec: &mut F) {
-        self.play_ym3_frame(rec);
-        let frame = &self.frames[self.cursor];
+    fn play_ym6_frame<F: FnMut(f32, u8, u8)>(&mut self, song: &SongData, rec: &mut F) {
+        self.play_ym3_frame(song, rec);
+        let frame = &song.frames[self.cursor];
 
         let fx0 = frame.fx0().fx6_channel().and_then(|(fx, chan)|
             frame.timer_divisor0().map(|div| (fx, chan, div, frame.vol(chan)))
@@ -116,54 +206,37 @@ impl YmSong {
             frame.timer_divisor1().map(|div| (fx, chan, div, frame.vol(chan)))
         );
         if let Some((fx, chan, divisor, vol)) = fx0 {
-            self.fx_update(fx, chan, divisor, vol);
+            self.fx_update(song, fx, chan, divisor, vol);
         }
         if let Some((fx, chan, divisor, vol)) = fx1 {
-            self.fx_update(fx, chan, divisor, vol);
+            self.fx_update(song, fx, chan, divisor, vol);
         }
     }
 
-    /// Produces the changes to the AY/YM chipset registers for the current frame indicated by
-    /// the cursor and advances the cursor forward one frame.
-    ///
-    /// Provide a function that receives 3 arguments:
-    /// * The timestamp as a cycle relative to the current frame, where `0.0` is the
-    ///   beginning of a frame. The timestamp will be always larger than `0.0` and less than the
-    ///   value returned from [YmSong::frame_cycles].
-    /// * The modified register's number `[0, 13]`.
-    /// * The modified register's new value.
-    ///
-    /// The changes are always being provided in the ascending order of the timestamp.
-    ///
-    /// Returns `true` if this was the last frame before the cursor has been set to the loop frame.
-    /// Otherwise returns `false`.
-    ///
-    /// This method can be used to populate changes to the AY/YM chipset or an emulator, to play
-    /// the YM-file song.
-    pub fn produce_next_ay_frame<F: FnMut(f32, u8, u8)>(&mut self, mut rec: F) -> bool {
+    pub fn produce_next_ay_frame<F: FnMut(f32, u8, u8)>(&mut self, song: &SongData, mut rec: F) -> bool {
         for (sv, ss, ..) in self.voice_effects.iter_mut() {
             sv.stop();
             ss.stop();
         }
         self.buzzer.stop();
 
-        match self.version {
-            YmVersion::Ym2 => self.play_ym2_frame(&mut rec),
-            YmVersion::Ym3 => self.play_ym3_frame(&mut rec),
+        match song.version {
+            YmVersion::Ym2 => self.play_ym2_frame(song, &mut rec),
+            YmVersion::Ym3 => self.play_ym3_frame(song, &mut rec),
             YmVersion::Ym4|
-            YmVersion::Ym5 => self.play_ym5_frame(&mut rec),
-            YmVersion::Ym6 => self.play_ym6_frame(&mut rec),
+            YmVersion::Ym5 => self.play_ym5_frame(song, &mut rec),
+            YmVersion::Ym6 => self.play_ym6_frame(song, &mut rec),
         }
 
         let cursor = self.cursor;
-        let frame = self.frames[cursor];
+        let frame = song.frames[cursor];
         for (val, reg) in frame.data.iter().copied().zip(0..MIXER_REG) {
             rec(0.0, reg, val);
         }
 
         let mut chan_mix = frame.data[MIXER_REG as usize];
 
-        let frame_cycles = self.frame_cycles();
+        let frame_cycles = song.frame_cycles();
         let mut voice_effects = &mut self.voice_effects[..];
         let mut frm_iters: [(Option<_>, Option<_>, Option<_>); 3] = Default::default();
         let mut tgt = frm_iters.iter_mut();
@@ -180,7 +253,7 @@ impl YmSong {
             }
             else if let Some(iter) = dd.iter_frame(frame_cycles,
                                                     reg,
-                                                    &self.dd_samples,
+                                                    song.dd_samples,
                                                     frame.vol(reg))
             {
                 chan_mix |= chan_mask;
@@ -206,10 +279,10 @@ impl YmSong {
             rec(ts, reg, val)
         }
 
-        let nframes = self.frames.len();
+        let nframes = song.frames.len();
         match (cursor + 1) % nframes {
             0 => {
-                self.cursor = (self.loop_frame as usize).min(nframes - 1);
+                self.cursor = (song.loop_frame as usize).min(nframes - 1);
                 true
             }
             cursor => {
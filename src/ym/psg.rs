@@ -0,0 +1,246 @@
+//! PSG (ZX Spectrum / fMSX) register dump conversion.
+use std::borrow::Cow;
+use std::io::{self, Read, Write};
+
+use log::warn;
+
+use super::*;
+
+/// The AY-3-8912 clock frequency of the ZX Spectrum 128K, assumed for `PSG` files.
+pub const PSG_CHIPSET_FREQUENCY: u32 = 1_773_400;
+/// The frame frequency of `PSG` files.
+pub const PSG_FRAME_FREQUENCY: u16 = 50;
+
+const PSG_SIGNATURE: &[u8;4] = b"PSG\x1a";
+const PSG_HEADER_SIZE: usize = 16;
+/// Ends the current frame.
+const PSG_FRAME: u8 = 0xff;
+/// Followed by `n`, skips `4 * n` frames.
+const PSG_SKIP: u8 = 0xfe;
+/// Ends the music data.
+const PSG_END: u8 = 0xfd;
+
+impl YmSong {
+    /// Attempts to parse a `PSG` register dump from the given stream source.
+    ///
+    /// Provide `file_name` which will be used as the song title.
+    ///
+    /// The new song is a `YM6!` song without special effects, with the ZX Spectrum
+    /// [PSG_CHIPSET_FREQUENCY] and a [PSG_FRAME_FREQUENCY] frame rate. Writes to the I/O port
+    /// registers 14 and 15 are ignored.
+    pub fn parse_psg<R, S>(mut rd: R, file_name: S) -> io::Result<YmSong>
+        where R: Read, S: Into<String>
+    {
+        let mut data = Vec::new();
+        rd.read_to_end(&mut data)?;
+        if data.len() < PSG_HEADER_SIZE || !data.starts_with(PSG_SIGNATURE) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognized file signature"))
+        }
+        let mut regs = [0u8;16];
+        regs[ENV_REG as usize] = 0xff;
+        let mut frames = Vec::new();
+        // whether any register was written since the last frame has been pushed
+        let mut pending = false;
+        let mut push_frames = |regs: &mut [u8;16], count: usize, pending: &mut bool| {
+            // ignore waits before the first register write
+            if frames.is_empty() && !*pending {
+                return
+            }
+            let mut frame = YmFrame { data: *regs };
+            frame.data[14] = 0;
+            frame.data[15] = 0;
+            frames.push(frame);
+            frame.data[ENV_REG as usize] = 0xff;
+            frames.extend(core::iter::repeat_n(frame, count - 1));
+            regs[ENV_REG as usize] = 0xff;
+            *pending = false;
+        };
+        let mut bytes = data[PSG_HEADER_SIZE..].iter().copied();
+        while let Some(cmd) = bytes.next() {
+            match cmd {
+                PSG_FRAME => push_frames(&mut regs, 1, &mut pending),
+                PSG_SKIP => {
+                    let count = bytes.next().unwrap_or(0) as usize * 4;
+                    if count != 0 {
+                        push_frames(&mut regs, count, &mut pending);
+                    }
+                }
+                PSG_END => break,
+                reg @ 0..=15 => {
+                    let val = bytes.next().ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely")
+                    })?;
+                    regs[reg as usize] = val;
+                    pending = true;
+                }
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid PSG register"))
+            }
+        }
+        if pending {
+            push_frames(&mut regs, 1, &mut pending);
+        }
+        if frames.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no frames"))
+        }
+        for frame in frames.iter_mut() {
+            clear_unused_bits(&mut frame.data);
+        }
+        Ok(YmSong::new(YmVersion::Ym6, frames.into_boxed_slice(), 0, file_name.into(), None)
+                  .with_frequency(PSG_CHIPSET_FREQUENCY, PSG_FRAME_FREQUENCY))
+    }
+
+    /// Writes the song as a `PSG` register dump to the given stream.
+    ///
+    /// The song is retuned to the [PSG_CHIPSET_FREQUENCY] and resampled to the
+    /// [PSG_FRAME_FREQUENCY] if necessary. Only the changed registers are written in each frame,
+    /// except for the envelope shape, which is written whenever it's being restarted.
+    ///
+    /// The YM-specific special effects can't be represented in this format and are dropped: the
+    /// volume of a channel playing a `DIGI-DRUM` sample is set to `0` and the `SID voice`,
+    /// `Sinus SID` and `Sync Buzzer` channels play their base volume. A warning is logged if
+    /// any effects were dropped. The song's loop is not preserved.
+    pub fn write_psg<W: Write>(&self, wr: W) -> io::Result<()> {
+        let dump = self.retimed(PSG_CHIPSET_FREQUENCY, PSG_FRAME_FREQUENCY).ay_register_dump();
+
+        let mut wr = io::BufWriter::new(wr);
+        let mut header = [0u8;PSG_HEADER_SIZE];
+        header[..4].copy_from_slice(PSG_SIGNATURE);
+        wr.write_all(&header)?;
+
+        let mut last: [Option<u8>;14] = [None;14];
+        let mut waits = 0usize;
+        for regs in dump.iter() {
+            let mut changes = Vec::new();
+            for (reg, (&val, last)) in regs.iter().zip(last.iter_mut()).enumerate() {
+                let retrigger = reg == ENV_REG as usize && val != 0xff;
+                if retrigger || reg != ENV_REG as usize && *last != Some(val) {
+                    changes.extend_from_slice(&[reg as u8, val]);
+                    *last = Some(val);
+                }
+            }
+            if !changes.is_empty() {
+                write_psg_waits(waits, wr.by_ref())?;
+                waits = 0;
+                wr.write_all(&changes)?;
+            }
+            waits += 1;
+        }
+        write_psg_waits(waits, wr.by_ref())?;
+        wr.write_all(&[PSG_END])?;
+        wr.flush()
+    }
+
+    /// Returns the song resampled to the `frame_frequency` and retuned to the `chipset_frequency`
+    /// if necessary. A warning is logged if any periods were out of range after retuning.
+    pub(super) fn retimed(&self, chipset_frequency: u32, frame_frequency: u16) -> Cow<'_, YmSong> {
+        let mut song = Cow::Borrowed(self);
+        if song.frame_frequency != frame_frequency {
+            song = Cow::Owned(song.resample(frame_frequency, Default::default()));
+        }
+        if song.chipset_frequency != chipset_frequency {
+            let clamped = song.to_mut().retune_to_clock(chipset_frequency).clamped().count();
+            if clamped != 0 {
+                warn!("WARNING: {} periods out of range after retuning", clamped);
+            }
+        }
+        song
+    }
+
+    /// Returns the AY/YM register values of every frame, see [YmSong::ay_registers].
+    /// A warning is logged if any special effects were dropped.
+    pub(super) fn ay_register_dump(&self) -> Vec<[u8;14]> {
+        let mut fx_frames = 0usize;
+        let dump = self.frames.iter().map(|frame| {
+            let (regs, dropped_fx) = self.ay_registers(frame);
+            fx_frames += dropped_fx as usize;
+            regs
+        }).collect();
+        if fx_frames != 0 {
+            warn!("WARNING: special effects dropped in {} frames", fx_frames);
+        }
+        dump
+    }
+
+    /// Returns the AY/YM register values of the `frame` without special effects, with `0xff`
+    /// in the register 13 if the envelope shape is unchanged, and whether any effect was dropped.
+    pub(super) fn ay_registers(&self, frame: &YmFrame) -> ([u8;14], bool) {
+        let mut regs = [0u8;14];
+        regs.copy_from_slice(&frame.data[..14]);
+        clear_unused_bits(&mut regs);
+        let mut dropped = false;
+        if self.version == YmVersion::Ym2 {
+            regs[ENV_PER_COARSE_REG as usize] = 0;
+            if regs[ENV_REG as usize] != 0xff {
+                regs[ENV_REG as usize] = 0x10;
+            }
+            if frame.data[VOL_C_REG as usize] & 0x80 != 0 {
+                regs[VOL_C_REG as usize] = 0;
+                dropped = true;
+            }
+        }
+        for (fx, chan, _) in self.frame_effects(frame).into_iter().flatten() {
+            if fx == FxType::DigiDrum {
+                regs[(VOL_A_REG + chan) as usize] = 0;
+            }
+            dropped = true;
+        }
+        (regs, dropped)
+    }
+}
+
+/// Clears the bits of AY/YM registers 0 to 10, that are unused by the chipset.
+pub(super) fn clear_unused_bits(regs: &mut [u8]) {
+    for reg in [1, 3, 5] {
+        regs[reg] &= 0x0f;
+    }
+    regs[NOISE_PER_REG as usize] &= 0x1f;
+    regs[MIXER_REG as usize] &= 0x3f;
+    for reg in VOL_A_REG..=VOL_C_REG {
+        regs[reg as usize] &= 0x1f;
+    }
+}
+
+fn write_psg_waits<W: Write>(mut waits: usize, mut wr: W) -> io::Result<()> {
+    while waits >= 4 {
+        let skip = (waits / 4).min(u8::MAX as usize);
+        wr.write_all(&[PSG_SKIP, skip as u8])?;
+        waits -= skip * 4;
+    }
+    for _ in 0..waits {
+        wr.write_all(&[PSG_FRAME])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn psg_roundtrip_works() {
+        let frames = [1u8, 1, 1, 1, 1, 1, 2, 3].iter().enumerate().map(|(index, &v)| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(0, v as u16 * 100);
+            frame.data[VOL_A_REG as usize] = 15;
+            frame.data[ENV_REG as usize] = if index == 0 || index == 7 { 0x0e } else { 0xff };
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 0, String::new(), None)
+                         .with_frequency(PSG_CHIPSET_FREQUENCY, PSG_FRAME_FREQUENCY);
+        let mut psg = Vec::new();
+        song.write_psg(&mut psg).unwrap();
+        assert_eq!(&psg[16..20], &[0, 100, 1, 0]);
+        // 6 frames, 1 frame, the last frame and the end marker
+        assert!(psg.ends_with(&[PSG_SKIP, 1, PSG_FRAME, PSG_FRAME, 0, 200,
+                                PSG_FRAME, 0, 44, 1, 1, 13, 0x0e,
+                                PSG_FRAME, PSG_END]));
+        let parsed = YmSong::parse_psg(&psg[..], "song").unwrap();
+        assert_eq!(parsed.version, YmVersion::Ym6);
+        assert_eq!(parsed.title, "song");
+        assert_eq!(parsed.chipset_frequency, PSG_CHIPSET_FREQUENCY);
+        assert_eq!(parsed.frames.len(), 8);
+        for (a, b) in parsed.frames.iter().zip(song.frames.iter()) {
+            assert_eq!(a.data[..14], b.data[..14]);
+        }
+    }
+}
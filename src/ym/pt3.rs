@@ -0,0 +1,703 @@
+//! ProTracker 3 (PT3) module import.
+//!
+//! The module is being played by the built-in replay routine, following the original ProTracker
+//! 3.x and Vortex Tracker II players, producing a frame of the AY registers every 1/50th of
+//! a second.
+//!
+//! The differences between the PT3 player versions (tone tables, volume table, glissando and
+//! portamento quirks) are selected by the version digit from the module header.
+use std::io::{self, Read};
+
+use super::*;
+use super::psg::PSG_CHIPSET_FREQUENCY;
+
+/// The frame frequency of the PT3 player.
+pub const PT3_FRAME_FREQUENCY: u16 = 50;
+
+const PT3_HEADER_SIZE: usize = 201;
+/// The maximum number of frames produced before giving up on a malformed module.
+const PT3_MAX_FRAMES: usize = 50 * 60 * 60;
+
+const PT3_NOTE_TABLE_PT_33_34R: [u16;96] = [
+    0x0C21, 0x0B73, 0x0ACE, 0x0A33, 0x09A0, 0x0916, 0x0893, 0x0818, 0x07A4, 0x0736, 0x06CE, 0x066D,
+    0x0610, 0x05B9, 0x0567, 0x0519, 0x04D0, 0x048B, 0x0449, 0x040C, 0x03D2, 0x039B, 0x0367, 0x0336,
+    0x0308, 0x02DC, 0x02B3, 0x028C, 0x0268, 0x0245, 0x0224, 0x0206, 0x01E9, 0x01CD, 0x01B3, 0x019B,
+    0x0184, 0x016E, 0x0159, 0x0146, 0x0134, 0x0122, 0x0112, 0x0103, 0x00F4, 0x00E6, 0x00D9, 0x00CD,
+    0x00C2, 0x00B7, 0x00AC, 0x00A3, 0x009A, 0x0091, 0x0089, 0x0081, 0x007A, 0x0073, 0x006C, 0x0066,
+    0x0061, 0x005B, 0x0056, 0x0051, 0x004D, 0x0048, 0x0044, 0x0040, 0x003D, 0x0039, 0x0036, 0x0033,
+    0x0030, 0x002D, 0x002B, 0x0028, 0x0026, 0x0024, 0x0022, 0x0020, 0x001E, 0x001C, 0x001B, 0x0019,
+    0x0018, 0x0016, 0x0015, 0x0014, 0x0013, 0x0012, 0x0011, 0x0010, 0x000F, 0x000E, 0x000D, 0x000C
+];
+
+const PT3_NOTE_TABLE_PT_34_35: [u16;96] = [
+    0x0C22, 0x0B73, 0x0ACF, 0x0A33, 0x09A1, 0x0917, 0x0894, 0x0819, 0x07A4, 0x0737, 0x06CF, 0x066D,
+    0x0611, 0x05BA, 0x0567, 0x051A, 0x04D0, 0x048B, 0x044A, 0x040C, 0x03D2, 0x039B, 0x0367, 0x0337,
+    0x0308, 0x02DD, 0x02B4, 0x028D, 0x0268, 0x0246, 0x0225, 0x0206, 0x01E9, 0x01CE, 0x01B4, 0x019B,
+    0x0184, 0x016E, 0x015A, 0x0146, 0x0134, 0x0123, 0x0112, 0x0103, 0x00F5, 0x00E7, 0x00DA, 0x00CE,
+    0x00C2, 0x00B7, 0x00AD, 0x00A3, 0x009A, 0x0091, 0x0089, 0x0082, 0x007A, 0x0073, 0x006D, 0x0067,
+    0x0061, 0x005C, 0x0056, 0x0052, 0x004D, 0x0049, 0x0045, 0x0041, 0x003D, 0x003A, 0x0036, 0x0033,
+    0x0031, 0x002E, 0x002B, 0x0029, 0x0027, 0x0024, 0x0022, 0x0020, 0x001F, 0x001D, 0x001B, 0x001A,
+    0x0018, 0x0017, 0x0016, 0x0014, 0x0013, 0x0012, 0x0011, 0x0010, 0x000F, 0x000E, 0x000D, 0x000C
+];
+
+const PT3_NOTE_TABLE_ST: [u16;96] = [
+    0x0EF8, 0x0E10, 0x0D60, 0x0C80, 0x0BD8, 0x0B28, 0x0A88, 0x09F0, 0x0960, 0x08E0, 0x0858, 0x07E0,
+    0x077C, 0x0708, 0x06B0, 0x0640, 0x05EC, 0x0594, 0x0544, 0x04F8, 0x04B0, 0x0470, 0x042C, 0x03FD,
+    0x03BE, 0x0384, 0x0358, 0x0320, 0x02F6, 0x02CA, 0x02A2, 0x027C, 0x0258, 0x0238, 0x0216, 0x01F8,
+    0x01DF, 0x01C2, 0x01AC, 0x0190, 0x017B, 0x0165, 0x0151, 0x013E, 0x012C, 0x011C, 0x010A, 0x00FC,
+    0x00EF, 0x00E1, 0x00D6, 0x00C8, 0x00BD, 0x00B2, 0x00A8, 0x009F, 0x0096, 0x008E, 0x0085, 0x007E,
+    0x0077, 0x0070, 0x006B, 0x0064, 0x005E, 0x0059, 0x0054, 0x004F, 0x004B, 0x0047, 0x0042, 0x003F,
+    0x003B, 0x0038, 0x0035, 0x0032, 0x002F, 0x002C, 0x002A, 0x0027, 0x0025, 0x0023, 0x0021, 0x001F,
+    0x001D, 0x001C, 0x001A, 0x0019, 0x0017, 0x0016, 0x0015, 0x0013, 0x0012, 0x0011, 0x0010, 0x000F
+];
+
+const PT3_NOTE_TABLE_ASM_34R: [u16;96] = [
+    0x0D3E, 0x0C80, 0x0BCC, 0x0B22, 0x0A82, 0x09EC, 0x095C, 0x08D6, 0x0858, 0x07E0, 0x076E, 0x0704,
+    0x069F, 0x0640, 0x05E6, 0x0591, 0x0541, 0x04F6, 0x04AE, 0x046B, 0x042C, 0x03F0, 0x03B7, 0x0382,
+    0x034F, 0x0320, 0x02F3, 0x02C8, 0x02A1, 0x027B, 0x0257, 0x0236, 0x0216, 0x01F8, 0x01DC, 0x01C1,
+    0x01A8, 0x0190, 0x0179, 0x0164, 0x0150, 0x013D, 0x012C, 0x011B, 0x010B, 0x00FC, 0x00EE, 0x00E0,
+    0x00D4, 0x00C8, 0x00BD, 0x00B2, 0x00A8, 0x009F, 0x0096, 0x008D, 0x0085, 0x007E, 0x0077, 0x0070,
+    0x006A, 0x0064, 0x005E, 0x0059, 0x0054, 0x004F, 0x004B, 0x0047, 0x0043, 0x003F, 0x003B, 0x0038,
+    0x0035, 0x0032, 0x002F, 0x002D, 0x002A, 0x0028, 0x0025, 0x0023, 0x0021, 0x001F, 0x001E, 0x001C,
+    0x001A, 0x0019, 0x0018, 0x0016, 0x0015, 0x0014, 0x0013, 0x0012, 0x0011, 0x0010, 0x000F, 0x000E
+];
+
+const PT3_NOTE_TABLE_ASM_34_35: [u16;96] = [
+    0x0D10, 0x0C55, 0x0BA4, 0x0AFC, 0x0A5F, 0x09CA, 0x093D, 0x08B8, 0x083B, 0x07C5, 0x0755, 0x06EC,
+    0x0688, 0x062A, 0x05D2, 0x057E, 0x052F, 0x04E5, 0x049E, 0x045C, 0x041D, 0x03E2, 0x03AB, 0x0376,
+    0x0344, 0x0315, 0x02E9, 0x02BF, 0x0298, 0x0272, 0x024F, 0x022E, 0x020F, 0x01F1, 0x01D5, 0x01BB,
+    0x01A2, 0x018B, 0x0174, 0x0160, 0x014C, 0x0139, 0x0128, 0x0117, 0x0107, 0x00F9, 0x00EB, 0x00DD,
+    0x00D1, 0x00C5, 0x00BA, 0x00B0, 0x00A6, 0x009D, 0x0094, 0x008C, 0x0084, 0x007C, 0x0075, 0x006F,
+    0x0069, 0x0063, 0x005D, 0x0058, 0x0053, 0x004E, 0x004A, 0x0046, 0x0042, 0x003E, 0x003B, 0x0037,
+    0x0034, 0x0031, 0x002F, 0x002C, 0x0029, 0x0027, 0x0025, 0x0023, 0x0021, 0x001F, 0x001D, 0x001C,
+    0x001A, 0x0019, 0x0017, 0x0016, 0x0015, 0x0014, 0x0012, 0x0011, 0x0010, 0x000F, 0x000E, 0x000D
+];
+
+const PT3_NOTE_TABLE_REAL_34R: [u16;96] = [
+    0x0CDA, 0x0C22, 0x0B73, 0x0ACF, 0x0A33, 0x09A1, 0x0917, 0x0894, 0x0819, 0x07A4, 0x0737, 0x06CF,
+    0x066D, 0x0611, 0x05BA, 0x0567, 0x051A, 0x04D0, 0x048B, 0x044A, 0x040C, 0x03D2, 0x039B, 0x0367,
+    0x0337, 0x0308, 0x02DD, 0x02B4, 0x028D, 0x0268, 0x0246, 0x0225, 0x0206, 0x01E9, 0x01CE, 0x01B4,
+    0x019B, 0x0184, 0x016E, 0x015A, 0x0146, 0x0134, 0x0123, 0x0112, 0x0103, 0x00F5, 0x00E7, 0x00DA,
+    0x00CE, 0x00C2, 0x00B7, 0x00AD, 0x00A3, 0x009A, 0x0091, 0x0089, 0x0082, 0x007A, 0x0073, 0x006D,
+    0x0067, 0x0061, 0x005C, 0x0056, 0x0052, 0x004D, 0x0049, 0x0045, 0x0041, 0x003D, 0x003A, 0x0036,
+    0x0033, 0x0031, 0x002E, 0x002B, 0x0029, 0x0027, 0x0024, 0x0022, 0x0020, 0x001F, 0x001D, 0x001B,
+    0x001A, 0x0018, 0x0017, 0x0016, 0x0014, 0x0013, 0x0012, 0x0011, 0x0010, 0x000F, 0x000E, 0x000D
+];
+
+const PT3_NOTE_TABLE_REAL_34_35: [u16;96] = [
+    0x0CDA, 0x0C22, 0x0B73, 0x0ACF, 0x0A33, 0x09A1, 0x0917, 0x0894, 0x0819, 0x07A4, 0x0737, 0x06CF,
+    0x066D, 0x0611, 0x05BA, 0x0567, 0x051A, 0x04D0, 0x048B, 0x044A, 0x040C, 0x03D2, 0x039B, 0x0367,
+    0x0337, 0x0308, 0x02DD, 0x02B4, 0x028D, 0x0268, 0x0246, 0x0225, 0x0206, 0x01E9, 0x01CE, 0x01B4,
+    0x019B, 0x0184, 0x016E, 0x015A, 0x0146, 0x0134, 0x0123, 0x0113, 0x0103, 0x00F5, 0x00E7, 0x00DA,
+    0x00CE, 0x00C2, 0x00B7, 0x00AD, 0x00A3, 0x009A, 0x0091, 0x0089, 0x0082, 0x007A, 0x0073, 0x006D,
+    0x0067, 0x0061, 0x005C, 0x0056, 0x0052, 0x004D, 0x0049, 0x0045, 0x0041, 0x003D, 0x003A, 0x0036,
+    0x0033, 0x0031, 0x002E, 0x002B, 0x0029, 0x0027, 0x0024, 0x0022, 0x0020, 0x001F, 0x001D, 0x001B,
+    0x001A, 0x0018, 0x0017, 0x0016, 0x0014, 0x0013, 0x0012, 0x0011, 0x0010, 0x000F, 0x000E, 0x000D
+];
+
+/// Selects the tone table by the table number from the module header and the player version.
+fn note_table(table: u8, version: u8) -> &'static [u16;96] {
+    match (table, version <= 3) {
+        (0, true) => &PT3_NOTE_TABLE_PT_33_34R,
+        (0, false) => &PT3_NOTE_TABLE_PT_34_35,
+        (1, _) => &PT3_NOTE_TABLE_ST,
+        (2, true) => &PT3_NOTE_TABLE_ASM_34R,
+        (2, false) => &PT3_NOTE_TABLE_ASM_34_35,
+        (_, true) => &PT3_NOTE_TABLE_REAL_34R,
+        (_, false) => &PT3_NOTE_TABLE_REAL_34_35
+    }
+}
+
+/// Creates the channel volume table the same way as the player does on initialization.
+///
+/// The PT3.5+ players round the results, the older ones truncate them.
+fn volume_table(version: u8) -> [[u8;16];16] {
+    let rounding = version >= 5;
+    let base: u16 = if rounding { 0x11 } else { 0x10 };
+    let mut table = [[0u8;16];16];
+    let mut step: u16 = if rounding { 0 } else { 0x10 };
+    for row in table[1..].iter_mut() {
+        step += base;
+        let mut acc: u16 = 0;
+        for val in row.iter_mut() {
+            *val = ((acc >> 8) + (rounding && acc & 0x80 != 0) as u16) as u8;
+            acc += step;
+        }
+        if step & 0xff == 0x77 {
+            step += 1;
+        }
+    }
+    table
+}
+
+/// The module data accessor, reading zeroes past the end of data.
+struct Pt3Data<'a> {
+    data: &'a [u8],
+    overrun: bool,
+}
+
+impl Pt3Data<'_> {
+    fn byte(&mut self, index: usize) -> u8 {
+        match self.data.get(index) {
+            Some(&byte) => byte,
+            None => {
+                self.overrun = true;
+                0
+            }
+        }
+    }
+
+    fn word(&mut self, index: usize) -> u16 {
+        u16::from_le_bytes([self.byte(index), self.byte(index + 1)])
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Pt3Channel {
+    address_in_pattern: usize,
+    ornament_pointer: usize,
+    sample_pointer: usize,
+    ton: u16,
+    loop_ornament_position: u8,
+    ornament_length: u8,
+    position_in_ornament: u8,
+    loop_sample_position: u8,
+    sample_length: u8,
+    position_in_sample: u8,
+    volume: u8,
+    number_of_notes_to_skip: u8,
+    note: u8,
+    slide_to_note: u8,
+    amplitude: u8,
+    envelope_enabled: bool,
+    enabled: bool,
+    simple_gliss: bool,
+    current_amplitude_sliding: i8,
+    current_noise_sliding: u8,
+    current_envelope_sliding: i8,
+    ton_slide_count: u8,
+    current_onoff: u8,
+    onoff_delay: u8,
+    offon_delay: u8,
+    ton_slide_delay: u8,
+    current_ton_sliding: i16,
+    ton_accumulator: i16,
+    ton_slide_step: i16,
+    ton_delta: i16,
+    note_skip_counter: u8,
+}
+
+impl Pt3Channel {
+    fn reset_note(&mut self) {
+        self.position_in_sample = 0;
+        self.current_amplitude_sliding = 0;
+        self.current_noise_sliding = 0;
+        self.current_envelope_sliding = 0;
+        self.position_in_ornament = 0;
+        self.ton_slide_count = 0;
+        self.current_ton_sliding = 0;
+        self.ton_accumulator = 0;
+        self.current_onoff = 0;
+    }
+}
+
+struct Pt3Player<'a> {
+    data: Pt3Data<'a>,
+    version: u8,
+    note_table: &'static [u16;96],
+    volume_table: [[u8;16];16],
+    number_of_positions: u8,
+    loop_position: u8,
+    patterns_pointer: usize,
+    env_base: u16,
+    cur_env_slide: i16,
+    env_slide_add: i16,
+    cur_env_delay: u8,
+    env_delay: u8,
+    noise_base: u8,
+    add_to_noise: u8,
+    delay: u8,
+    delay_counter: u8,
+    current_position: u8,
+    env_type: Option<u8>,
+    channels: [Pt3Channel;3],
+}
+
+impl<'a> Pt3Player<'a> {
+    fn new(data: &'a [u8], version: u8) -> Pt3Player<'a> {
+        let mut data = Pt3Data { data, overrun: false };
+        let mut player = Pt3Player {
+            version,
+            note_table: note_table(data.byte(99), version),
+            volume_table: volume_table(version),
+            number_of_positions: data.byte(101),
+            loop_position: data.byte(102),
+            patterns_pointer: data.word(103) as usize,
+            env_base: 0,
+            cur_env_slide: 0,
+            env_slide_add: 0,
+            cur_env_delay: 0,
+            env_delay: 0,
+            noise_base: 0,
+            add_to_noise: 0,
+            delay: data.byte(100),
+            delay_counter: 1,
+            current_position: 0,
+            env_type: None,
+            channels: Default::default(),
+            data,
+        };
+        for chan in 0..3 {
+            let ch = &mut player.channels[chan];
+            ch.volume = 15;
+            ch.note_skip_counter = 1;
+            ch.number_of_notes_to_skip = 1;
+            player.set_ornament(chan, 0);
+            player.set_sample(chan, 1);
+        }
+        player.set_position(0);
+        player
+    }
+
+    fn set_position(&mut self, position: u8) {
+        let pattern = self.data.byte(PT3_HEADER_SIZE + position as usize) as usize;
+        for chan in 0..3 {
+            self.channels[chan].address_in_pattern =
+                self.data.word(self.patterns_pointer + (pattern + chan) * 2) as usize;
+        }
+    }
+
+    fn set_ornament(&mut self, chan: usize, ornament: u8) {
+        let pointer = self.data.word(169 + (ornament as usize & 15) * 2) as usize;
+        let loop_position = self.data.byte(pointer);
+        let length = self.data.byte(pointer + 1);
+        let ch = &mut self.channels[chan];
+        ch.loop_ornament_position = loop_position;
+        ch.ornament_length = length;
+        ch.ornament_pointer = pointer + 2;
+    }
+
+    fn set_sample(&mut self, chan: usize, sample: u8) {
+        let pointer = self.data.word(105 + (sample as usize & 31) * 2) as usize;
+        let loop_position = self.data.byte(pointer);
+        let length = self.data.byte(pointer + 1);
+        let ch = &mut self.channels[chan];
+        ch.loop_sample_position = loop_position;
+        ch.sample_length = length;
+        ch.sample_pointer = pointer + 2;
+    }
+
+    fn next_byte(&mut self, chan: usize) -> u8 {
+        let address = self.channels[chan].address_in_pattern;
+        self.channels[chan].address_in_pattern += 1;
+        self.data.byte(address)
+    }
+
+    fn next_word(&mut self, chan: usize) -> u16 {
+        u16::from_le_bytes([self.next_byte(chan), self.next_byte(chan)])
+    }
+
+    fn next_env_base(&mut self, chan: usize) -> u16 {
+        u16::from_be_bytes([self.next_byte(chan), self.next_byte(chan)])
+    }
+
+    fn pattern_interpreter(&mut self, chan: usize) {
+        let prev_note = self.channels[chan].note;
+        let prev_sliding = self.channels[chan].current_ton_sliding;
+        let mut commands = Vec::new();
+        loop {
+            let val = self.next_byte(chan);
+            match val {
+                0xF0..=0xFF => {
+                    self.set_ornament(chan, val - 0xF0);
+                    let sample = self.next_byte(chan) / 2;
+                    self.set_sample(chan, sample);
+                    let ch = &mut self.channels[chan];
+                    ch.envelope_enabled = false;
+                    ch.position_in_ornament = 0;
+                }
+                0xD1..=0xEF => self.set_sample(chan, val - 0xD0),
+                0xD0 => break,
+                0xC1..=0xCF => self.channels[chan].volume = val - 0xC0,
+                0xC0 => {
+                    let ch = &mut self.channels[chan];
+                    ch.reset_note();
+                    ch.enabled = false;
+                    break
+                }
+                0xB2..=0xBF => {
+                    self.env_type = Some(val - 0xB1);
+                    self.env_base = self.next_env_base(chan);
+                    self.cur_env_slide = 0;
+                    self.cur_env_delay = 0;
+                    let ch = &mut self.channels[chan];
+                    ch.envelope_enabled = true;
+                    ch.position_in_ornament = 0;
+                }
+                0xB1 => self.channels[chan].number_of_notes_to_skip = self.next_byte(chan),
+                0xB0 => {
+                    let ch = &mut self.channels[chan];
+                    ch.envelope_enabled = false;
+                    ch.position_in_ornament = 0;
+                }
+                0x50..=0xAF => {
+                    let ch = &mut self.channels[chan];
+                    ch.note = val - 0x50;
+                    ch.reset_note();
+                    ch.enabled = true;
+                    break
+                }
+                0x40..=0x4F => {
+                    self.set_ornament(chan, val - 0x40);
+                    self.channels[chan].position_in_ornament = 0;
+                }
+                0x20..=0x3F => self.noise_base = val - 0x20,
+                0x10..=0x1F => {
+                    if val == 0x10 {
+                        self.channels[chan].envelope_enabled = false;
+                    }
+                    else {
+                        self.env_type = Some(val - 0x10);
+                        self.env_base = self.next_env_base(chan);
+                        self.cur_env_slide = 0;
+                        self.cur_env_delay = 0;
+                        self.channels[chan].envelope_enabled = true;
+                    }
+                    let sample = self.next_byte(chan) / 2;
+                    self.set_sample(chan, sample);
+                    self.channels[chan].position_in_ornament = 0;
+                }
+                0x01..=0x05|0x08|0x09 => commands.push(val),
+                _ => {}
+            }
+        }
+        // the effect parameters follow the note in the reverse order of the commands,
+        // only the last one of the repeated commands is being recognized
+        for (index, &command) in commands.iter().enumerate().rev() {
+            if commands.iter().rposition(|&c| c == command) != Some(index) {
+                continue
+            }
+            match command {
+                0x01 => {
+                    let delay = self.next_byte(chan);
+                    let step = self.next_word(chan) as i16;
+                    let ch = &mut self.channels[chan];
+                    ch.ton_slide_delay = delay;
+                    ch.ton_slide_count = delay;
+                    ch.ton_slide_step = step;
+                    ch.simple_gliss = true;
+                    ch.current_onoff = 0;
+                    if ch.ton_slide_count == 0 && self.version >= 7 {
+                        ch.ton_slide_count += 1;
+                    }
+                }
+                0x02 => {
+                    let delay = self.next_byte(chan);
+                    self.next_word(chan);
+                    let step = (self.next_word(chan) as i16).wrapping_abs();
+                    let note_table = self.note_table;
+                    let ch = &mut self.channels[chan];
+                    ch.simple_gliss = false;
+                    ch.current_onoff = 0;
+                    ch.ton_slide_delay = delay;
+                    ch.ton_slide_count = delay;
+                    ch.ton_slide_step = step;
+                    ch.ton_delta = note_table[ch.note.min(95) as usize] as i16
+                                 - note_table[prev_note.min(95) as usize] as i16;
+                    ch.slide_to_note = ch.note;
+                    ch.note = prev_note;
+                    if self.version >= 6 {
+                        ch.current_ton_sliding = prev_sliding;
+                    }
+                    if ch.ton_delta.wrapping_sub(ch.current_ton_sliding) < 0 {
+                        ch.ton_slide_step = -ch.ton_slide_step;
+                    }
+                }
+                0x03 => self.channels[chan].position_in_sample = self.next_byte(chan),
+                0x04 => self.channels[chan].position_in_ornament = self.next_byte(chan),
+                0x05 => {
+                    let onoff = self.next_byte(chan);
+                    let offon = self.next_byte(chan);
+                    let ch = &mut self.channels[chan];
+                    ch.onoff_delay = onoff;
+                    ch.offon_delay = offon;
+                    ch.current_onoff = onoff;
+                    ch.ton_slide_count = 0;
+                    ch.current_ton_sliding = 0;
+                }
+                0x08 => {
+                    self.env_delay = self.next_byte(chan);
+                    self.cur_env_delay = self.env_delay;
+                    self.env_slide_add = self.next_word(chan) as i16;
+                }
+                0x09 => self.delay = self.next_byte(chan),
+                _ => unreachable!()
+            }
+        }
+        let ch = &mut self.channels[chan];
+        ch.note_skip_counter = ch.number_of_notes_to_skip;
+    }
+
+    /// Updates the channel state for the next frame, returns the envelope period addition.
+    fn change_registers(&mut self, chan: usize, mixer: &mut u8) -> i16 {
+        let mut add_to_env = 0;
+        if self.channels[chan].enabled {
+            let ch = self.channels[chan];
+            let sample_ptr = ch.sample_pointer + ch.position_in_sample as usize * 4;
+            let b0 = self.data.byte(sample_ptr);
+            let b1 = self.data.byte(sample_ptr + 1);
+            let sample_ton = self.data.word(sample_ptr + 2) as i16;
+            let ornament = self.data.byte(ch.ornament_pointer + ch.position_in_ornament as usize) as i8;
+            let note_table = self.note_table;
+            let volume_table = &self.volume_table;
+            let ch = &mut self.channels[chan];
+
+            let ton = sample_ton.wrapping_add(ch.ton_accumulator);
+            if b1 & 0x40 != 0 {
+                ch.ton_accumulator = ton;
+            }
+            let note = (ch.note as i16 + ornament as i16).clamp(0, 95);
+            ch.ton = (ton.wrapping_add(ch.current_ton_sliding)
+                         .wrapping_add(note_table[note as usize] as i16) as u16) & 0x0fff;
+            if ch.ton_slide_count > 0 {
+                ch.ton_slide_count -= 1;
+                if ch.ton_slide_count == 0 {
+                    ch.current_ton_sliding = ch.current_ton_sliding.wrapping_add(ch.ton_slide_step);
+                    ch.ton_slide_count = ch.ton_slide_delay;
+                    if !ch.simple_gliss && (
+                        (ch.ton_slide_step < 0 && ch.current_ton_sliding <= ch.ton_delta) ||
+                        (ch.ton_slide_step >= 0 && ch.current_ton_sliding >= ch.ton_delta))
+                    {
+                        ch.note = ch.slide_to_note;
+                        ch.ton_slide_count = 0;
+                        ch.current_ton_sliding = 0;
+                    }
+                }
+            }
+
+            if b0 & 0x80 != 0 {
+                if b0 & 0x40 != 0 {
+                    if ch.current_amplitude_sliding < 15 {
+                        ch.current_amplitude_sliding += 1;
+                    }
+                }
+                else if ch.current_amplitude_sliding > -15 {
+                    ch.current_amplitude_sliding -= 1;
+                }
+            }
+            let amplitude = ((b1 & 15) as i8 + ch.current_amplitude_sliding).clamp(0, 15);
+            ch.amplitude = volume_table[ch.volume as usize & 15][amplitude as usize];
+            if b0 & 1 == 0 && ch.envelope_enabled {
+                ch.amplitude |= 0x10;
+            }
+
+            if b1 & 0x80 != 0 {
+                let slide = if b0 & 0x20 != 0 {
+                    ((b0 >> 1) | 0xF0) as i8
+                }
+                else {
+                    ((b0 >> 1) & 0x0F) as i8
+                }.wrapping_add(ch.current_envelope_sliding);
+                if b1 & 0x20 != 0 {
+                    ch.current_envelope_sliding = slide;
+                }
+                add_to_env = slide as i16;
+            }
+            else {
+                self.add_to_noise = (b0 >> 1).wrapping_add(ch.current_noise_sliding);
+                if b1 & 0x20 != 0 {
+                    ch.current_noise_sliding = self.add_to_noise;
+                }
+            }
+            *mixer |= (b1 >> 1) & 0x48;
+
+            ch.position_in_sample += 1;
+            if ch.position_in_sample >= ch.sample_length {
+                ch.position_in_sample = ch.loop_sample_position;
+            }
+            ch.position_in_ornament += 1;
+            if ch.position_in_ornament >= ch.ornament_length {
+                ch.position_in_ornament = ch.loop_ornament_position;
+            }
+        }
+        else {
+            self.channels[chan].amplitude = 0;
+        }
+        *mixer >>= 1;
+        let ch = &mut self.channels[chan];
+        if ch.current_onoff > 0 {
+            ch.current_onoff -= 1;
+            if ch.current_onoff == 0 {
+                ch.enabled = !ch.enabled;
+                ch.current_onoff = if ch.enabled { ch.onoff_delay } else { ch.offon_delay };
+            }
+        }
+        add_to_env
+    }
+
+    /// Plays the next frame. Returns `None` if the song has ended and the player looped to
+    /// the loop position instead.
+    fn play_frame(&mut self) -> Option<YmFrame> {
+        self.delay_counter = self.delay_counter.wrapping_sub(1);
+        if self.delay_counter == 0 {
+            let mut looped = false;
+            for chan in 0..3 {
+                let ch = &mut self.channels[chan];
+                ch.note_skip_counter = ch.note_skip_counter.wrapping_sub(1);
+                if ch.note_skip_counter != 0 {
+                    continue
+                }
+                if chan == 0 && self.data.byte(ch.address_in_pattern) == 0 {
+                    self.current_position += 1;
+                    if self.current_position >= self.number_of_positions {
+                        self.current_position = self.loop_position;
+                        looped = true;
+                    }
+                    self.set_position(self.current_position);
+                    self.noise_base = 0;
+                }
+                self.pattern_interpreter(chan);
+            }
+            self.delay_counter = self.delay;
+            if looped {
+                return None
+            }
+        }
+
+        let mut frame = YmFrame::default();
+        let mut mixer = 0;
+        let mut add_to_env: i16 = 0;
+        for chan in 0..3 {
+            add_to_env = add_to_env.wrapping_add(self.change_registers(chan, &mut mixer));
+            let ch = &self.channels[chan];
+            frame.set_tone_period(chan as u8, ch.ton);
+            frame.data[VOL_A_REG as usize + chan] = ch.amplitude;
+        }
+        frame.data[MIXER_REG as usize] = mixer;
+        frame.data[NOISE_PER_REG as usize] = self.noise_base.wrapping_add(self.add_to_noise) & 0x1f;
+        frame.set_env_period((self.env_base as i16).wrapping_add(add_to_env)
+                                                   .wrapping_add(self.cur_env_slide) as u16);
+        frame.data[ENV_REG as usize] = self.env_type.take().unwrap_or(0xff);
+
+        if self.cur_env_delay > 0 {
+            self.cur_env_delay -= 1;
+            if self.cur_env_delay == 0 {
+                self.cur_env_delay = self.env_delay;
+                self.cur_env_slide = self.cur_env_slide.wrapping_add(self.env_slide_add);
+            }
+        }
+        Some(frame)
+    }
+}
+
+fn header_text(bytes: &[u8]) -> String {
+    let len = bytes.iter().rposition(|&b| b != b' ' && b != 0).map_or(0, |pos| pos + 1);
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+impl YmSong {
+    /// Attempts to parse a ProTracker 3 (`PT3`) module from the given stream source.
+    ///
+    /// The module is being played by the built-in PT3 player and the new song is a `YM6!` song
+    /// with the ZX Spectrum [PSG_CHIPSET_FREQUENCY] and a [PT3_FRAME_FREQUENCY] frame rate.
+    /// The title and the author are taken from the module header.
+    ///
+    /// The song ends where the player reaches the end of the last position and its loop frame
+    /// is the first frame of the loop position.
+    pub fn parse_pt3<R: Read>(mut rd: R) -> io::Result<YmSong> {
+        let mut data = Vec::new();
+        rd.read_to_end(&mut data)?;
+        if data.len() < PT3_HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely"))
+        }
+        let (version, tracker) = if data.starts_with(b"ProTracker 3.") {
+            let version = match data[13] {
+                digit@b'0'..=b'9' => digit - b'0',
+                _ => 6
+            };
+            (version, format!("ProTracker 3.{}", version))
+        }
+        else if data.starts_with(b"Vortex Tracker II") {
+            (6, "Vortex Tracker II".into())
+        }
+        else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognized file signature"))
+        };
+        let number_of_positions = data[101];
+        if number_of_positions == 0 || data[102] >= number_of_positions {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid PT3 position list"))
+        }
+
+        let mut player = Pt3Player::new(&data, version);
+        let mut position_frames = [None;256];
+        let mut frames = Vec::new();
+        while let Some(frame) = player.play_frame() {
+            position_frames[player.current_position as usize].get_or_insert(frames.len());
+            frames.push(frame);
+            if frames.len() > PT3_MAX_FRAMES || player.data.overrun {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid PT3 pattern data"))
+            }
+        }
+        if frames.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no PT3 data"))
+        }
+        let loop_frame = position_frames[player.loop_position as usize].unwrap_or(0) as u32;
+        let mut song = YmSong::new(YmVersion::Ym6, frames.into_boxed_slice(), loop_frame,
+                                   header_text(&data[30..62]), None)
+                              .with_meta(header_text(&data[66..98]), String::new())
+                              .with_frequency(PSG_CHIPSET_FREQUENCY, PT3_FRAME_FREQUENCY);
+        song.tracker = tracker;
+        song.chip_type = ChipType::Ay;
+        Ok(song)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_pt3_works() {
+        let mut pt3 = vec![b' ';PT3_HEADER_SIZE];
+        pt3[..30].copy_from_slice(b"ProTracker 3.7 compilation of ");
+        pt3[30..34].copy_from_slice(b"Tune");
+        pt3[62..66].copy_from_slice(b" by ");
+        pt3[66..72].copy_from_slice(b"Author");
+        pt3[99..103].copy_from_slice(&[0, 3, 1, 0]); // tone table, delay, positions, loop
+        pt3[103..PT3_HEADER_SIZE].fill(0);
+        pt3.extend_from_slice(&[0, 0xff]); // position list
+        let patterns = pt3.len();
+        pt3[103..105].copy_from_slice(&(patterns as u16).to_le_bytes());
+        pt3.resize(patterns + 6, 0);
+        let channel_a = pt3.len();
+        // sample 1, envelope 0x0e of period 0x0120 with a slide, note C-4, empty line, end
+        pt3.extend_from_slice(&[0xD1, 0x08, 0xBF, 0x01, 0x20, 0x50 + 48, 1, 0xff, 0xff, 0xD0, 0]);
+        let channel_bc = pt3.len();
+        pt3.extend_from_slice(&[0xD0, 0xD0, 0]);
+        for (index, address) in [channel_a, channel_bc, channel_bc].into_iter().enumerate() {
+            let at = patterns + index * 2;
+            pt3[at..at + 2].copy_from_slice(&(address as u16).to_le_bytes());
+        }
+        let sample = pt3.len();
+        pt3[107..109].copy_from_slice(&(sample as u16).to_le_bytes());
+        pt3.extend_from_slice(&[0, 1, 0x00, 0x8F, 0, 0]); // tone, envelope, volume 15
+        let ornament = pt3.len();
+        pt3[169..171].copy_from_slice(&(ornament as u16).to_le_bytes());
+        pt3.extend_from_slice(&[0, 1, 0]);
+
+        let song = YmSong::parse_pt3(&pt3[..]).unwrap();
+        assert_eq!((song.title.as_str(), song.author.as_str()), ("Tune", "Author"));
+        assert_eq!(song.tracker, "ProTracker 3.7");
+        assert_eq!((song.chipset_frequency, song.frame_frequency), (1_773_400, 50));
+        assert_eq!((song.frames.len(), song.loop_frame), (6, 0));
+        let frame = &song.frames[0];
+        assert_eq!(frame.tone_period(0), 0x00C2);
+        assert_eq!(frame.data[VOL_A_REG as usize], 0x1f);
+        assert_eq!(frame.data[MIXER_REG as usize], 0x08);
+        assert_eq!(frame.data[ENV_REG as usize], 0x0e);
+        assert_eq!(song.frames[1].data[ENV_REG as usize], 0xff);
+        let env_periods: Vec<_> = song.frames.iter().map(|frame| frame.env_period()).collect();
+        assert_eq!(env_periods, [0x120, 0x11f, 0x11e, 0x11d, 0x11c, 0x11b]);
+        assert!(YmSong::parse_pt3(&pt3[..100]).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+//! Frame rate conversion.
+use super::*;
+
+/// The method of re-timing register states, used by [YmSong::resample].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleMode {
+    /// Each new frame is a copy of the original frame nearest in time.
+    ///
+    /// One-shot events, like envelope shape writes and effect starts, are being removed from
+    /// the repeated copies of the same original frame. The events of the skipped original frames
+    /// are moved onto the next new frame, but only the last envelope shape write is preserved.
+    Nearest,
+    /// Each new frame gets the state of the last original frame that began before the next
+    /// new frame.
+    ///
+    /// Every `DIGI-DRUM` start is being emitted exactly once. Each new frame retriggers the
+    /// envelope (register 13) if any of its original frames does. If more than one envelope shape
+    /// write falls into a single new frame, the first one is emitted and the last one is deferred
+    /// to the next new frame, but only if that frame has no envelope shape writes of its own,
+    /// otherwise only the last one is emitted. The writes in between are collapsed, so the
+    /// retriggers are never delayed by more than a single frame and the last written shape
+    /// is always preserved.
+    #[default]
+    PreserveRetriggers,
+}
+
+impl YmSong {
+    /// Creates a new song from this one, re-timed to be played at the given `frame_frequency`.
+    ///
+    /// The `loop_frame` is being remapped to the nearest new frame.
+    ///
+    /// # Panics
+    /// Panics if `frame_frequency` is `0`.
+    pub fn resample(&self, frame_frequency: u16, mode: ResampleMode) -> YmSong {
+        assert_ne!(frame_frequency, 0, "frame frequency must not be 0");
+        let old_rate = self.frame_frequency as u64;
+        let new_rate = frame_frequency as u64;
+        let nframes = self.frames.len() as u64;
+        let new_nframes = (nframes * new_rate).div_ceil(old_rate).max(1);
+        let frames = match mode {
+            ResampleMode::Nearest => {
+                self.resample_nearest(new_nframes, old_rate, new_rate)
+            }
+            ResampleMode::PreserveRetriggers => {
+                self.resample_preserving(new_nframes, old_rate, new_rate)
+            }
+        };
+        let loop_frame = (self.loop_frame as u64 * new_rate * 2 + old_rate) / (old_rate * 2);
+        let loop_frame = loop_frame.min(new_nframes - 1) as u32;
+        YmSong::new(self.version, frames, loop_frame, self.title.clone(), self.created)
+               .with_meta(self.author.clone(), self.comments.clone())
+               .with_samples(self.song_attrs, self.dd_samples.clone(), self.dd_samples_ends)
+               .with_frequency(self.chipset_frequency, frame_frequency)
+    }
+
+    fn resample_nearest(&self, new_nframes: u64, old_rate: u64, new_rate: u64) -> Box<[YmFrame]> {
+        let nframes = self.frames.len() as u64;
+        let mut prev_source = None;
+        (0..new_nframes).map(|index| {
+            let source = ((index * old_rate * 2 + new_rate) / (new_rate * 2)).min(nframes - 1) as usize;
+            let mut frame = self.frames[source];
+            match prev_source {
+                Some(prev) if prev == source => {
+                    self.clear_frame_events(&mut frame);
+                }
+                prev => {
+                    let skipped = &self.frames[prev.map_or(0, |prev| prev + 1)..source];
+                    if frame.data[ENV_REG as usize] == 0xff {
+                        if let Some(src) = skipped.iter().rev().find(|f| f.data[ENV_REG as usize] != 0xff) {
+                            frame.data[ENV_REG as usize] = src.data[ENV_REG as usize];
+                        }
+                    }
+                    self.merge_frame_events(&mut frame, &self.frames[source], skipped);
+                }
+            }
+            prev_source = Some(source);
+            frame
+        }).collect()
+    }
+
+    fn resample_preserving(&self, new_nframes: u64, old_rate: u64, new_rate: u64) -> Box<[YmFrame]> {
+        let nframes = self.frames.len() as u64;
+        // original frames that begin before the next new frame
+        let ranges: Vec<&[YmFrame]> = (0..new_nframes).map(|index| {
+            let start = (index * old_rate).div_ceil(new_rate).min(nframes);
+            let end = ((index + 1) * old_rate).div_ceil(new_rate).min(nframes);
+            &self.frames[start as usize..end as usize]
+        }).collect();
+        let shapes = |sources: &[YmFrame]| -> Vec<u8> {
+            sources.iter().map(|f| f.data[ENV_REG as usize]).filter(|&shape| shape != 0xff).collect()
+        };
+        let mut deferred = None;
+        let mut frames: Vec<YmFrame> = Vec::with_capacity(new_nframes as usize);
+        for (index, &sources) in ranges.iter().enumerate() {
+            let mut frame = match sources.last() {
+                Some(last) => *last,
+                None => {
+                    let mut frame = *frames.last().unwrap_or(&self.frames[0]);
+                    self.clear_frame_events(&mut frame);
+                    frame
+                }
+            };
+            let written = shapes(sources);
+            let shape = match written.split_first() {
+                None => deferred.take(),
+                Some((&first, rest)) => {
+                    let next_is_free = ranges.get(index + 1)
+                                             .is_some_and(|next| shapes(next).is_empty());
+                    match rest.last() {
+                        Some(&last) if next_is_free => {
+                            deferred = Some(last);
+                            Some(first)
+                        }
+                        Some(&last) => Some(last),
+                        None => Some(first)
+                    }
+                }
+            };
+            frame.data[ENV_REG as usize] = shape.unwrap_or(0xff);
+            if let Some((last, rest)) = sources.split_last() {
+                self.merge_frame_events(&mut frame, last, rest);
+            }
+            frames.push(frame);
+        }
+        frames.into_boxed_slice()
+    }
+
+    /// Returns which effect slots of the frame start a `DIGI-DRUM` sample.
+    fn dd_start_slots(&self, frame: &YmFrame) -> [bool;2] {
+        self.dd_sample_regs(frame).map(|reg| reg.is_some())
+    }
+
+    /// Removes one-shot events from a repeated frame.
+    fn clear_frame_events(&self, frame: &mut YmFrame) {
+        frame.data[ENV_REG as usize] = 0xff;
+        match self.version {
+            YmVersion::Ym2 => frame.data[VOL_C_REG as usize] &= !0x80,
+            YmVersion::Ym3 => {}
+            YmVersion::Ym4|YmVersion::Ym5 => {
+                frame.data[1] &= !FxCtrlFlags::MFP_TIMER_RESTART.bits();
+            }
+            YmVersion::Ym6 => {}
+        }
+        for (slot, started) in self.dd_start_slots(frame).into_iter().enumerate() {
+            if started {
+                frame.data[1 + 2 * slot] &= !FxCtrlFlags::CHAN_CONTROL_MASK.bits();
+            }
+        }
+    }
+
+    /// Moves one-shot events from the earlier original frames in `rest` onto the `frame` that
+    /// was created from the `last` original frame.
+    fn merge_frame_events(&self, frame: &mut YmFrame, last: &YmFrame, rest: &[YmFrame]) {
+        match self.version {
+            YmVersion::Ym2 => {
+                if last.data[VOL_C_REG as usize] & 0x80 == 0 {
+                    if let Some(src) = rest.iter().rev().find(|f| f.data[VOL_C_REG as usize] & 0x80 != 0) {
+                        frame.data[VOL_C_REG as usize] = src.data[VOL_C_REG as usize];
+                        frame.data[ENV_PER_COARSE_REG as usize] = src.data[ENV_PER_COARSE_REG as usize];
+                    }
+                }
+            }
+            YmVersion::Ym3 => {}
+            YmVersion::Ym4|YmVersion::Ym5|YmVersion::Ym6 => {
+                let restart = FxCtrlFlags::MFP_TIMER_RESTART.bits();
+                if matches!(self.version, YmVersion::Ym4|YmVersion::Ym5)
+                   && rest.iter().any(|f| f.data[1] & restart != 0)
+                {
+                    frame.data[1] |= restart;
+                }
+                for (slot, started) in self.dd_start_slots(last).into_iter().enumerate() {
+                    if started {
+                        continue
+                    }
+                    if let Some(src) = rest.iter().rev().find(|f| self.dd_start_slots(f)[slot]) {
+                        copy_fx_slot(frame, src, slot);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Copies the special effect controlled by register 1 if `slot` is `0` or by register 3
+/// otherwise, with its timer setup and the effect data in the volume register.
+fn copy_fx_slot(frame: &mut YmFrame, src: &YmFrame, slot: usize) {
+    let (ctrl_reg, prediv_reg, div_reg) = match slot {
+        0 => (1, NOISE_PER_REG as usize, 14),
+        _ => (3, VOL_A_REG as usize, 15)
+    };
+    frame.data[ctrl_reg] = (src.data[ctrl_reg] & 0xf0) | (frame.data[ctrl_reg] & 0x0f);
+    frame.data[prediv_reg] = (src.data[prediv_reg] & 0xe0) | (frame.data[prediv_reg] & 0x1f);
+    frame.data[div_reg] = src.data[div_reg];
+    let flags = if slot == 0 { src.fx0() } else { src.fx1() };
+    if let Some(chan) = flags.dd_channel() {
+        let vol_reg = (VOL_A_REG + chan) as usize;
+        frame.data[vol_reg] = (frame.data[vol_reg] & 0xe0) | (src.data[vol_reg] & 0x1f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shapes(song: &YmSong) -> Vec<u8> {
+        song.frames.iter().map(|f| f.data[ENV_REG as usize]).collect()
+    }
+
+    #[test]
+    fn resample_preserves_retriggers() {
+        let frames = [0x0e, 0xff, 0x08, 0x0a, 0xff, 0xff].iter().map(|&shape| {
+            let mut frame = YmFrame::default();
+            frame.data[ENV_REG as usize] = shape;
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 4, String::new(), None);
+
+        let up = song.resample(100, ResampleMode::PreserveRetriggers);
+        assert_eq!(up.frame_frequency, 100);
+        assert_eq!(up.loop_frame, 8);
+        assert_eq!(shapes(&up), [0x0e, 0xff, 0xff, 0xff, 0x08, 0xff, 0x0a, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        let up = song.resample(100, ResampleMode::Nearest);
+        assert_eq!(shapes(&up), [0x0e, 0xff, 0xff, 0x08, 0xff, 0x0a, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+        let mut song = song;
+        song.frames[2].data[1] = FxCtrlFlags::MFP_TIMER_RESTART.bits();
+        let up = song.resample(100, ResampleMode::Nearest);
+        let restarts: Vec<bool> = up.frames.iter().map(|f| f.data[1] != 0).collect();
+        assert_eq!(restarts.iter().filter(|&&r| r).count(), 1);
+        assert!(restarts[3]);
+
+        let down = song.resample(25, ResampleMode::PreserveRetriggers);
+        assert_eq!(down.loop_frame, 2);
+        assert_eq!(shapes(&down), [0x0e, 0x08, 0x0a]);
+        let down = song.resample(25, ResampleMode::Nearest);
+        assert_eq!(shapes(&down), [0x0e, 0x08, 0x0a]);
+    }
+
+    #[test]
+    fn resample_collapses_dense_retriggers() {
+        let frames = (0..40u8).map(|n| {
+            let mut frame = YmFrame::default();
+            frame.data[ENV_REG as usize] = 8 + n % 8;
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 0, String::new(), None)
+                         .with_frequency(2_000_000, 200);
+        let down = song.resample(50, ResampleMode::PreserveRetriggers);
+        assert_eq!(down.frames.len(), 10);
+        let expected: Vec<u8> = (0..10).map(|n| 8 + (4 * n + 3) % 8).collect();
+        assert_eq!(shapes(&down), expected);
+    }
+}
@@ -0,0 +1,290 @@
+//! C and assembly source code export.
+//!
+//! The register data can be laid out as a separate array for each register, which packs
+//! better, or as a single array with all the registers of each frame in turn.
+//!
+//! With [SourceOptions::changed_only], the per-register arrays consist of pairs of bytes: the
+//! number of frames `[1, 255]` and the register value held for that many frames. The frame-major
+//! array holds a 16-bit mask of the changed registers for each frame, as two bytes with the bits
+//! of registers 0-7 and 8-15, followed by the values of the changed registers. All the registers
+//! are present in the first frame and in the loop frame.
+//!
+//! In both layouts the envelope shape register value `0xff` means no write, like in [YmFrame],
+//! and the envelope shape register is flagged as changed whenever it's written.
+use std::io::{self, Write};
+
+use super::*;
+
+/// The number of bytes in a single line of data.
+const BYTES_PER_LINE: usize = 16;
+
+/// The syntax of the exported source code.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SourceSyntax {
+    /// C `const uint8_t` arrays.
+    #[default]
+    C,
+    /// Motorola 68000 assembly with `dc.b` directives.
+    M68k,
+    /// Zilog Z80 assembly with `db` directives.
+    Z80,
+}
+
+/// The layout of the exported register data.
+///
+/// The arrays are named with the [SourceOptions::name] prefix: `<name>_r0` to `<name>_r15` for
+/// each register, or `<name>_data` for all of them, and `<name>_dd0` and so on for the samples.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SourceLayout {
+    /// A separate array for each register.
+    #[default]
+    PerRegister,
+    /// A single array with all the registers of each frame in turn.
+    FrameMajor,
+}
+
+/// The source code export options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceOptions {
+    /// The syntax of the source code.
+    pub syntax: SourceSyntax,
+    /// The layout of the register data.
+    pub layout: SourceLayout,
+    /// Whether only the changed registers are encoded.
+    pub changed_only: bool,
+    /// Whether the special effects are exported. If `true` all 16 registers of the frames are
+    /// exported as is, together with the `DIGI-DRUM` samples, otherwise only the 14 AY/YM
+    /// registers are, with the special effects dropped.
+    pub effects: bool,
+    /// The prefix of all the exported identifiers.
+    pub name: String,
+}
+
+impl Default for SourceOptions {
+    /// C source with per-register arrays of all frames, without special effects, named `song`.
+    fn default() -> Self {
+        SourceOptions {
+            syntax: SourceSyntax::C,
+            layout: SourceLayout::PerRegister,
+            changed_only: false,
+            effects: false,
+            name: "song".into(),
+        }
+    }
+}
+
+impl YmSong {
+    /// Writes the song as C or assembly source code to the given stream.
+    ///
+    /// The constants with the number of frames and the loop frame are exported together with the
+    /// register data. The loop position in each data array is marked with a label, suffixed with
+    /// `_loop`, in assembly or with a constant byte offset, suffixed with `_LOOP`, in C.
+    ///
+    /// `DIGI-DRUM` samples are exported with the 4-bit values, one in each byte.
+    pub fn write_source<W: Write>(&self, wr: W, options: &SourceOptions) -> io::Result<()> {
+        let nregs = if options.effects { 16 } else { 14 };
+        let dump: Vec<[u8;16]> = match options.effects {
+            true => self.frames.iter().map(|frame| frame.data).collect(),
+            false => self.ay_register_dump().into_iter().map(|regs| {
+                let mut data = [0u8;16];
+                data[..14].copy_from_slice(&regs);
+                data
+            }).collect()
+        };
+        let loop_frame = self.loop_frame as usize;
+
+        let mut src = SourceWriter { wr, syntax: options.syntax };
+        src.comment(&format!("{} - {}", self.title, self.author))?;
+        src.comment(&format!("{} song: {} frames, {} Hz chipset clock, {} Hz frame rate",
+            self.version, self.frames.len(), self.chipset_frequency, self.frame_frequency))?;
+        if options.syntax == SourceSyntax::C {
+            writeln!(src.wr, "#include <stdint.h>")?;
+        }
+        writeln!(src.wr)?;
+        let name = &options.name;
+        src.constant(&format!("{}_frames", name), self.frames.len())?;
+        src.constant(&format!("{}_loop_frame", name), loop_frame)?;
+        src.constant(&format!("{}_regs", name), nregs)?;
+        writeln!(src.wr)?;
+
+        match options.layout {
+            SourceLayout::PerRegister => {
+                for reg in 0..nregs {
+                    let values = dump.iter().map(|regs| regs[reg]);
+                    let (data, loop_offset) = if options.changed_only {
+                        encode_runs(values, loop_frame)
+                    }
+                    else {
+                        (values.collect(), Some(loop_frame))
+                    };
+                    src.array(&format!("{}_r{}", name, reg), &data, loop_offset)?;
+                }
+            }
+            SourceLayout::FrameMajor => {
+                let (data, loop_offset) = if options.changed_only {
+                    encode_changes(&dump, nregs, loop_frame)
+                }
+                else {
+                    (dump.iter().flat_map(|regs| regs[..nregs].iter().copied()).collect(),
+                     Some(loop_frame * nregs))
+                };
+                src.array(&format!("{}_data", name), &data, loop_offset)?;
+            }
+        }
+
+        if options.effects {
+            let nsamples = self.dd_samples_ends.iter().rposition(|&end| end != 0).map_or(0, |n| n + 1);
+            for sample in 0..nsamples {
+                let data = &self.dd_samples[self.sample_data_range(sample)];
+                let data: Vec<u8> = data.iter().map(|&smp| smp & 0x0f).collect();
+                src.array(&format!("{}_dd{}", name, sample), &data, None)?;
+            }
+        }
+        src.wr.flush()
+    }
+}
+
+/// Encodes `values` as pairs of the frame count and the value, starting a new pair at
+/// the loop frame. Returns the data and the loop offset.
+fn encode_runs<I: Iterator<Item=u8>>(values: I, loop_frame: usize) -> (Vec<u8>, Option<usize>) {
+    let mut data: Vec<u8> = Vec::new();
+    let mut loop_offset = None;
+    for (index, val) in values.enumerate() {
+        let len = data.len();
+        if index == loop_frame {
+            loop_offset = Some(len);
+        }
+        else if len != 0 && data[len - 1] == val && data[len - 2] != u8::MAX {
+            data[len - 2] += 1;
+            continue
+        }
+        data.extend_from_slice(&[1, val]);
+    }
+    (data, loop_offset)
+}
+
+/// Encodes frames as the changed register masks followed by the changed values. Returns the data
+/// and the loop offset.
+fn encode_changes(dump: &[[u8;16]], nregs: usize, loop_frame: usize) -> (Vec<u8>, Option<usize>) {
+    let mut data = Vec::new();
+    let mut loop_offset = None;
+    let mut last: Option<&[u8;16]> = None;
+    for (index, regs) in dump.iter().enumerate() {
+        if index == loop_frame {
+            loop_offset = Some(data.len());
+            last = None;
+        }
+        let mut mask = 0u16;
+        for reg in 0..nregs {
+            let changed = match reg as u8 {
+                ENV_REG => regs[reg] != 0xff,
+                _ => last.is_none_or(|last| last[reg] != regs[reg])
+            };
+            mask |= (changed as u16) << reg;
+        }
+        data.extend_from_slice(&mask.to_le_bytes());
+        data.extend((0..nregs).filter(|reg| mask & (1 << reg) != 0).map(|reg| regs[reg]));
+        last = Some(regs);
+    }
+    (data, loop_offset)
+}
+
+struct SourceWriter<W> {
+    wr: W,
+    syntax: SourceSyntax,
+}
+
+impl<W: Write> SourceWriter<W> {
+    fn comment(&mut self, text: &str) -> io::Result<()> {
+        let text = text.replace(['\r', '\n'], " ");
+        match self.syntax {
+            SourceSyntax::C => writeln!(self.wr, "/* {} */", text.replace("*/", "* /")),
+            SourceSyntax::M68k|SourceSyntax::Z80 => writeln!(self.wr, "; {}", text)
+        }
+    }
+
+    fn constant(&mut self, name: &str, value: usize) -> io::Result<()> {
+        match self.syntax {
+            SourceSyntax::C => writeln!(self.wr, "#define {} {}", name.to_uppercase(), value),
+            SourceSyntax::M68k|SourceSyntax::Z80 => writeln!(self.wr, "{} equ {}", name.to_uppercase(), value)
+        }
+    }
+
+    fn array(&mut self, name: &str, data: &[u8], loop_offset: Option<usize>) -> io::Result<()> {
+        let loop_offset = loop_offset.filter(|&offset| offset < data.len());
+        if self.syntax == SourceSyntax::C {
+            if let Some(offset) = loop_offset {
+                self.constant(&format!("{}_loop", name), offset)?;
+            }
+            writeln!(self.wr, "const uint8_t {}[{}] = {{", name, data.len())?;
+            for line in data.chunks(BYTES_PER_LINE) {
+                write!(self.wr, "   ")?;
+                for byte in line {
+                    write!(self.wr, " 0x{:02x},", byte)?;
+                }
+                writeln!(self.wr)?;
+            }
+            return writeln!(self.wr, "}};\n")
+        }
+        let directive = match self.syntax {
+            SourceSyntax::M68k => "dc.b",
+            _ => "db"
+        };
+        writeln!(self.wr, "{}:", name)?;
+        let (head, tail) = data.split_at(loop_offset.unwrap_or(data.len()));
+        for (index, part) in [head, tail].into_iter().enumerate() {
+            if index == 1 && loop_offset.is_some() {
+                writeln!(self.wr, "{}_loop:", name)?;
+            }
+            for line in part.chunks(BYTES_PER_LINE) {
+                let bytes: Vec<String> = line.iter().map(|byte| format!("${:02x}", byte)).collect();
+                writeln!(self.wr, "\t{} {}", directive, bytes.join(","))?;
+            }
+        }
+        if self.syntax == SourceSyntax::M68k {
+            writeln!(self.wr, "\teven")?;
+        }
+        writeln!(self.wr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_source_works() {
+        let frames = (0..4u32).map(|n| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(0, if n < 2 { 0x123 } else { 0x456 });
+            frame.data[ENV_REG as usize] = if n == 1 { 0x0a } else { 0xff };
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 1, "Tune".into(), None)
+                         .with_meta("Author".into(), String::new());
+        let mut c = Vec::new();
+        song.write_source(&mut c, &SourceOptions { changed_only: true, ..Default::default() }).unwrap();
+        let c = String::from_utf8(c).unwrap();
+        assert!(c.starts_with("/* Tune - Author */\n"));
+        assert!(c.contains("#define SONG_FRAMES 4\n#define SONG_LOOP_FRAME 1\n"));
+        assert!(c.contains("#define SONG_R0_LOOP 2\nconst uint8_t song_r0[6] = {\n    0x01, 0x23, 0x01, 0x23, 0x02, 0x56,\n};\n"));
+        assert!(c.contains("const uint8_t song_r13[6] = {\n    0x01, 0xff, 0x01, 0x0a, 0x02, 0xff,\n};\n"));
+
+        let options = SourceOptions {
+            syntax: SourceSyntax::Z80,
+            layout: SourceLayout::FrameMajor,
+            changed_only: true,
+            ..Default::default()
+        };
+        let mut z80 = Vec::new();
+        song.write_source(&mut z80, &options).unwrap();
+        let z80 = String::from_utf8(z80).unwrap();
+        assert!(z80.contains("SONG_REGS equ 14\n"));
+        assert!(z80.contains(concat!(
+            "song_data:\n",
+            "\tdb $ff,$1f,$23,$01,$00,$00,$00,$00,$00,$00,$00,$00,$00,$00,$00\n",
+            "song_data_loop:\n",
+            "\tdb $ff,$3f,$23,$01,$00,$00,$00,$00,$00,$00,$00,$00,$00,$00,$00,$0a\n",
+            "\tdb $03,$00,$56,$04,$00,$00\n")));
+    }
+}
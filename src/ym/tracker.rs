@@ -0,0 +1,328 @@
+//! YM-Tracker songs.
+//!
+//! The `YMT1` and `YMT2` files contain the sample based tracker data, mixed digitally by the player,
+//! instead of the AY/YM chipset registers.
+use core::time::Duration;
+use chrono::NaiveDateTime;
+
+use super::flags::SongAttributes;
+use super::DEFAULT_FRAME_FREQUENCY;
+
+/// The maximum number of voices of a YM-Tracker song.
+pub const MAX_TRACKER_VOICES: usize = 8;
+
+/// The fixed point precision of the sample position.
+const POS_PREC: u32 = 16;
+
+/// The YM-Tracker file version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YmTrackerVersion {
+    Ymt1,
+    /// Adds the sample repeat length and the frequency shift.
+    Ymt2,
+}
+
+impl YmTrackerVersion {
+    /// The YM-Tracker version identifier tag as a string (4 ascii characters).
+    pub fn tag(self) -> &'static str {
+        match self {
+            YmTrackerVersion::Ymt1 => "YMT1",
+            YmTrackerVersion::Ymt2 => "YMT2",
+        }
+    }
+}
+
+/// A YM-Tracker sample.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackerSample {
+    /// Unsigned 8-bit sample data.
+    pub data: Box<[u8]>,
+    /// The number of bytes at the end of the sample being repeated when the sample loops.
+    pub repeat_len: usize,
+}
+
+/// A single line of a YM-Tracker pattern for a single voice.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrackerLine {
+    /// The sample number starting a note or `0xff` if no note is started.
+    pub note: u8,
+    /// The volume in 6 lowest bits and the loop flag in bit 6.
+    pub volume: u8,
+    /// The sample playback frequency in Hz, `0` stops the voice.
+    pub freq: u16,
+}
+
+impl TrackerLine {
+    /// Returns the sample number if the line starts a note.
+    pub fn sample(self) -> Option<u8> {
+        match self.note {
+            0xff => None,
+            note => Some(note)
+        }
+    }
+
+    /// Returns the volume `[0, 63]`.
+    pub fn level(self) -> u8 {
+        self.volume & 0x3f
+    }
+
+    /// Returns `true` if the sample is being repeated.
+    pub fn is_looping(self) -> bool {
+        self.volume & 0x40 != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TrackerVoice {
+    running: bool,
+    sample: usize,
+    /// The sample position in [POS_PREC] fixed point.
+    pos: u64,
+    step_freq: u16,
+    level: u8,
+    looping: bool,
+}
+
+/// The **YM-Tracker** music file.
+#[derive(Debug, Clone)]
+pub struct YmTrackerSong {
+    /// YM-Tracker file version.
+    pub version: YmTrackerVersion,
+    /// The last modification timestamp of the file from the LHA envelope.
+    pub created: Option<NaiveDateTime>,
+    /// The song attributes.
+    pub song_attrs: SongAttributes,
+    /// The song title.
+    pub title: String,
+    /// The song author.
+    pub author: String,
+    /// The comment.
+    pub comments: String,
+    /// The number of pattern lines played each second.
+    pub frame_frequency: u16,
+    /// The loop line index.
+    pub loop_frame: u32,
+    /// The sample frequencies are multiplied by `2` to the power of this value (`YMT2` only).
+    pub freq_shift: u8,
+    /// The number of voices `[1, 8]`.
+    pub nvoices: u8,
+    /// The samples.
+    pub samples: Vec<TrackerSample>,
+    /// The pattern lines of all voices, `nvoices` lines for each frame.
+    pub lines: Box<[TrackerLine]>,
+        cursor: usize,
+        samples_before: u32,
+        voices: [TrackerVoice;MAX_TRACKER_VOICES],
+}
+
+impl YmTrackerSong {
+    /// Creates a new instance of `YmTrackerSong` from the given `lines` and other meta data.
+    ///
+    /// The `lines` consist of `nvoices` lines for each frame.
+    ///
+    /// # Panics
+    /// Panics if `nvoices` is `0` or larger than [MAX_TRACKER_VOICES] or if the number of `lines`
+    /// is not a multiple of `nvoices`.
+    pub fn new(
+            version: YmTrackerVersion,
+            nvoices: u8,
+            lines: Box<[TrackerLine]>,
+            loop_frame: u32,
+            title: String,
+            created: Option<NaiveDateTime>
+        ) -> YmTrackerSong
+    {
+        assert!(nvoices != 0 && nvoices as usize <= MAX_TRACKER_VOICES, "invalid number of voices");
+        assert!(lines.len().is_multiple_of(nvoices as usize), "invalid number of lines");
+        YmTrackerSong {
+            version,
+            created,
+            song_attrs: SongAttributes::default(),
+            title,
+            author: String::new(),
+            comments: String::new(),
+            frame_frequency: DEFAULT_FRAME_FREQUENCY,
+            loop_frame,
+            freq_shift: 0,
+            nvoices,
+            samples: Vec::new(),
+            lines,
+            cursor: 0,
+            samples_before: 0,
+            voices: Default::default()
+        }
+    }
+
+    /// Returns `YmTrackerSong` with the `samples`, the song attributes and the frequency shift
+    /// set from the given arguments.
+    pub fn with_samples(
+            mut self,
+            song_attrs: SongAttributes,
+            samples: Vec<TrackerSample>,
+            freq_shift: u8
+        ) -> YmTrackerSong
+    {
+        self.song_attrs = song_attrs;
+        self.samples = samples;
+        self.freq_shift = freq_shift;
+        self
+    }
+
+    /// Returns `YmTrackerSong` with the `author` and `comments` set from the given arguments.
+    pub fn with_meta(mut self, author: String, comments: String) -> YmTrackerSong {
+        self.author = author;
+        self.comments = comments;
+        self
+    }
+
+    /// Returns `YmTrackerSong` with the `frame_frequency` set from the given argument.
+    pub fn with_frame_frequency(mut self, frame_frequency: u16) -> YmTrackerSong {
+        self.frame_frequency = frame_frequency;
+        self
+    }
+
+    /// Returns the number of frames (pattern lines).
+    pub fn nframes(&self) -> usize {
+        self.lines.len() / self.nvoices as usize
+    }
+
+    /// Returns the lines of all voices of the given `frame`.
+    pub fn frame_lines(&self, frame: usize) -> &[TrackerLine] {
+        let nvoices = self.nvoices as usize;
+        &self.lines[frame * nvoices..(frame + 1) * nvoices]
+    }
+
+    /// Returns the song duration.
+    pub fn song_duration(&self) -> Duration {
+        let seconds = self.nframes() as f64 / self.frame_frequency as f64;
+        Duration::from_secs_f64(seconds)
+    }
+
+    /// Resets the state of the player.
+    pub fn reset(&mut self) {
+        self.cursor = 0;
+        self.samples_before = 0;
+        self.voices = Default::default();
+    }
+
+    /// Returns the current frame cursor value.
+    pub fn cursor(&self) -> u32 {
+        self.cursor as u32
+    }
+
+    /// Renders the song into the mono 16-bit PCM `buffer` at the given `sample_rate`.
+    ///
+    /// The song is being played from the current cursor, continuing from the loop frame after
+    /// the last frame. The voices are mixed the same way as in the StSound player, so the output
+    /// never exceeds the 16-bit range.
+    pub fn render(&mut self, mut buffer: &mut [i16], sample_rate: u32) {
+        buffer.fill(0);
+        let nframes = self.nframes();
+        if nframes == 0 || self.frame_frequency == 0 || sample_rate == 0 {
+            return
+        }
+        while !buffer.is_empty() {
+            if self.samples_before == 0 {
+                self.play_frame();
+                self.samples_before = (sample_rate / self.frame_frequency as u32).max(1);
+            }
+            let count = buffer.len().min(self.samples_before as usize);
+            self.samples_before -= count as u32;
+            let (chunk, rest) = buffer.split_at_mut(count);
+            for voice in 0..self.nvoices as usize {
+                self.add_voice(voice, chunk, sample_rate);
+            }
+            buffer = rest;
+        }
+    }
+
+    fn play_frame(&mut self) {
+        let nvoices = self.nvoices as usize;
+        let start = self.cursor * nvoices;
+        for (voice, line) in self.voices.iter_mut().zip(&self.lines[start..start + nvoices]) {
+            voice.step_freq = line.freq;
+            if line.freq == 0 {
+                voice.running = false;
+                continue
+            }
+            voice.level = line.level();
+            voice.looping = line.is_looping();
+            if let Some(sample) = line.sample() {
+                voice.running = (sample as usize) < self.samples.len();
+                voice.sample = sample as usize;
+                voice.pos = 0;
+            }
+        }
+        self.cursor += 1;
+        if self.cursor >= self.nframes() {
+            self.cursor = (self.loop_frame as usize).min(self.nframes() - 1);
+        }
+    }
+
+    fn add_voice(&mut self, voice: usize, chunk: &mut [i16], sample_rate: u32) {
+        let scale = 256 / self.nvoices as i32;
+        let voice = &mut self.voices[voice];
+        if !voice.running {
+            return
+        }
+        let sample = &self.samples[voice.sample];
+        let end = (sample.data.len() as u64) << POS_PREC;
+        let repeat_len = (sample.repeat_len.min(sample.data.len()) as u64) << POS_PREC;
+        let step = ((voice.step_freq as u64) << POS_PREC << self.freq_shift) / sample_rate as u64;
+        let level = voice.level as i32;
+        for out in chunk.iter_mut() {
+            if voice.pos >= end {
+                if voice.looping && repeat_len != 0 {
+                    voice.pos -= repeat_len * ((voice.pos - end) / repeat_len + 1);
+                }
+                else {
+                    voice.running = false;
+                    return
+                }
+            }
+            let s = sample.data[(voice.pos >> POS_PREC) as usize] as i32 - 128;
+            *out = out.saturating_add((s * scale * level / 64) as i16);
+            voice.pos += step;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::{StSoundSong, YmSong};
+    use super::*;
+
+    #[test]
+    fn parse_ymt_works() {
+        let mut ymt = Vec::new();
+        ymt.extend_from_slice(b"YMT1LeOnArD!");
+        ymt.extend_from_slice(&1u16.to_be_bytes()); // voices
+        ymt.extend_from_slice(&10u16.to_be_bytes()); // player rate
+        ymt.extend_from_slice(&2u32.to_be_bytes()); // frames
+        ymt.extend_from_slice(&1u32.to_be_bytes()); // loop frame
+        ymt.extend_from_slice(&1u16.to_be_bytes()); // samples
+        ymt.extend_from_slice(&0u32.to_be_bytes()); // attributes
+        ymt.extend_from_slice(b"Title\0Author\0\0");
+        ymt.extend_from_slice(&4u16.to_be_bytes());
+        ymt.extend_from_slice(&[0x80, 0xc0, 0x80, 0x40]);
+        ymt.extend_from_slice(&[0, 0x3f | 0x40, 0, 100]);
+        ymt.extend_from_slice(&[0xff, 32 | 0x40, 0, 100]);
+        let err = YmSong::parse_any(Cursor::new(ymt.clone()), "tune").unwrap_err();
+        assert!(err.to_string().contains("StSoundSong::parse_any"));
+        let StSoundSong::Tracker(mut song) = StSoundSong::parse_any(Cursor::new(ymt), "tune").unwrap() else {
+            panic!("not a tracker song")
+        };
+        assert_eq!(song.version, YmTrackerVersion::Ymt1);
+        assert_eq!((song.title.as_str(), song.author.as_str()), ("Title", "Author"));
+        assert_eq!((song.nframes(), song.loop_frame, song.frame_frequency), (2, 1, 10));
+        assert_eq!(song.samples[0].repeat_len, 4);
+        assert_eq!(song.frame_lines(1), &[TrackerLine { note: 0xff, volume: 0x60, freq: 100 }]);
+        assert_eq!(song.song_duration(), Duration::from_millis(200));
+        let mut buffer = [0i16;16];
+        song.render(&mut buffer, 100);
+        assert_eq!(&buffer[..10], &[0, 16128, 0, -16128, 0, 16128, 0, -16128, 0, 16128]);
+        assert_eq!(&buffer[10..], &[0, -8192, 0, 8192, 0, -8192]);
+        assert_eq!(song.cursor(), 1);
+    }
+}
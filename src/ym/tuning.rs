@@ -0,0 +1,370 @@
+//! Pitch related song transformations.
+use core::iter;
+use std::collections::HashMap;
+
+use super::*;
+
+const MAX_TONE_PERIOD: u32 = 0x0fff;
+const MAX_NOISE_PERIOD: u32 = 0x1f;
+const MAX_ENV_PERIOD: u32 = 0xffff;
+/// In `YM2!` songs the register 12 is reserved for the `DIGI-DRUM` pre-divisor.
+const MAX_YM2_ENV_PERIOD: u32 = 0xff;
+const MAX_YM2_DD_DIVISOR: u32 = 0xff;
+
+/// Identifies the period value being transformed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeriodTarget {
+    /// The tone period of the voice channel `[0, 2]`.
+    Tone(u8),
+    /// The noise period.
+    Noise,
+    /// The envelope period.
+    Envelope,
+    /// The timer divisor of the special effect.
+    Timer(FxType),
+}
+
+/// Selects what else should be affected by [YmSong::transpose_cents].
+///
+/// Tone and envelope periods and the timers of the `SID voice`, `Sinus SID` and `Sync Buzzer`
+/// effects are always being transposed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TransposeOptions {
+    /// Transpose noise periods.
+    pub noise: bool,
+    /// Transpose the playback rate of `DIGI-DRUM` samples.
+    pub digi_drums: bool,
+}
+
+/// A period value that could not be represented exactly after the transformation.
+///
+/// The same `original` period of the same `target` is being reported only once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InexactPeriod {
+    /// What kind of period is being reported.
+    pub target: PeriodTarget,
+    /// The period value before the transformation.
+    pub original: u32,
+    /// The ideal period value after the transformation.
+    pub wanted: f64,
+    /// The period value that was actually stored.
+    pub actual: u32,
+    /// Whether the wanted period was out of range and had to be clamped.
+    pub clamped: bool,
+    /// The index of the first frame where the `original` period was found.
+    pub first_frame: u32,
+    /// The number of frames where the `original` period was found.
+    pub frames: u32,
+}
+
+/// The result of pitch related transformations.
+#[derive(Debug, Default, Clone)]
+pub struct TuningReport {
+    /// The periods that could not be represented exactly, in the order of their first appearance.
+    pub inexact: Vec<InexactPeriod>,
+}
+
+/// Rounds and clamps scaled periods, collecting the inexact ones.
+#[derive(Debug)]
+struct PeriodScaler {
+    ratio: f64,
+    report: TuningReport,
+    index: HashMap<(PeriodTarget, u32), usize>,
+}
+
+impl InexactPeriod {
+    /// Returns `true` if the wanted period was out of range and had to be clamped.
+    pub fn is_clamped(&self) -> bool {
+        self.clamped
+    }
+
+    /// Returns the pitch deviation of the stored period from the wanted one in cents.
+    ///
+    /// A positive value indicates that the resulting pitch is higher than intended.
+    pub fn cents_error(&self) -> f64 {
+        if self.actual == 0 {
+            return 0.0
+        }
+        1200.0 * (self.wanted / self.actual as f64).log2()
+    }
+}
+
+impl TuningReport {
+    /// Returns `true` if all periods were represented exactly.
+    pub fn is_exact(&self) -> bool {
+        self.inexact.is_empty()
+    }
+
+    /// Returns an iterator of the periods that were out of range.
+    pub fn clamped(&self) -> impl Iterator<Item=&InexactPeriod> {
+        self.inexact.iter().filter(|p| p.is_clamped())
+    }
+
+    /// Returns the largest absolute pitch deviation in cents among the reported periods.
+    pub fn max_cents_error(&self) -> f64 {
+        self.inexact.iter().map(|p| p.cents_error().abs()).fold(0.0, f64::max)
+    }
+}
+
+impl PeriodScaler {
+    fn new(ratio: f64) -> Self {
+        PeriodScaler { ratio, report: TuningReport::default(), index: HashMap::new() }
+    }
+
+    /// Scales the `period`, clamping the result to `[1, max]`. A period of `0` is left as is.
+    fn scale(&mut self, frame: usize, target: PeriodTarget, period: u32, max: u32) -> u32 {
+        if period == 0 {
+            return 0
+        }
+        let wanted = period as f64 * self.ratio;
+        let rounded = wanted.round();
+        let actual = rounded.clamp(1.0, max as f64) as u32;
+        self.check(frame, target, period, wanted, actual, rounded != actual as f64);
+        actual
+    }
+
+    /// Scales the timer divisor encoded as `prediv3` and `div8`, returning the new encoding.
+    ///
+    /// The original pre-divisor is preserved if possible.
+    fn scale_timer(&mut self, frame: usize, fx: FxType, prediv3: u8, div8: u8) -> (u8, u8) {
+        let divisor = match calculate_timer_divisor(prediv3, div8) {
+            Some(divisor) => divisor.get(),
+            None => return (prediv3, div8)
+        };
+        let wanted = divisor as f64 * self.ratio;
+        let prediv_index = (prediv3 >> 5) as usize - 1;
+        let candidates = iter::once(prediv_index).chain(0..TIMER_PREDIVISORS.len());
+        let (index, div, clamped) = nearest_timer_divisor(wanted, candidates);
+        let actual = TIMER_PREDIVISORS[index] * div;
+        self.check(frame, PeriodTarget::Timer(fx), divisor, wanted, actual, clamped);
+        (((index as u8 + 1) << 5) | (prediv3 & 0x1f), div as u8)
+    }
+
+    fn check(
+            &mut self,
+            frame: usize,
+            target: PeriodTarget,
+            period: u32,
+            wanted: f64,
+            actual: u32,
+            clamped: bool
+        )
+    {
+        if (actual as f64 - wanted).abs() > 1e-6 {
+            let inexact = &mut self.report.inexact;
+            let index = *self.index.entry((target, period)).or_insert_with(|| {
+                inexact.push(InexactPeriod {
+                    target,
+                    original: period,
+                    wanted,
+                    actual,
+                    clamped,
+                    first_frame: frame as u32,
+                    frames: 0
+                });
+                inexact.len() - 1
+            });
+            inexact[index].frames += 1;
+        }
+    }
+
+    fn into_report(self) -> TuningReport {
+        self.report
+    }
+}
+
+impl YmSong {
+    /// Converts the song to be played on the AY/YM chipset with the given `chipset_frequency`
+    /// without changing its pitch.
+    ///
+    /// Tone, noise and envelope periods of every frame are being rescaled with rounding and clamped
+    /// to their valid ranges. Special effects are timed by the MFP timer, which runs independently
+    /// of the chipset clock, so their timer divisors are already expressed in real time and are
+    /// kept as they are. This keeps the pitch of the `SID voice`, `Sinus SID` and `Sync Buzzer`
+    /// effects intact.
+    ///
+    /// Returns a report of periods that could not be represented exactly.
+    ///
+    /// # Panics
+    /// Panics if `chipset_frequency` is `0`.
+    pub fn retune_to_clock(&mut self, chipset_frequency: u32) -> TuningReport {
+        assert_ne!(chipset_frequency, 0, "chipset frequency must not be 0");
+        let ratio = chipset_frequency as f64 / self.chipset_frequency as f64;
+        let mut scaler = PeriodScaler::new(ratio);
+        self.scale_periods(&mut scaler, true);
+        self.chipset_frequency = chipset_frequency;
+        scaler.into_report()
+    }
+
+    /// Transposes the song by the given number of `semitones`.
+    ///
+    /// A positive number raises the pitch. See [YmSong::transpose_cents].
+    pub fn transpose(&mut self, semitones: i32, options: TransposeOptions) -> TuningReport {
+        self.transpose_cents(semitones as f64 * 100.0, options)
+    }
+
+    /// Transposes the song by the given number of `cents`.
+    ///
+    /// A positive number raises the pitch.
+    ///
+    /// Tone periods in registers 0 to 5 and envelope periods in registers 11 and 12 are being
+    /// scaled, so the pitch of the envelope based "buzzer" sounds follows the tone. The timer
+    /// divisors of the `SID voice`, `Sinus SID` and `Sync Buzzer` effects are being adjusted
+    /// accordingly. Noise periods and the `DIGI-DRUM` playback rate are left alone unless
+    /// requested by `options`.
+    ///
+    /// Values that fall out of range are clamped instead of being wrapped and are reported
+    /// together with the other periods that could not be represented exactly.
+    pub fn transpose_cents(&mut self, cents: f64, options: TransposeOptions) -> TuningReport {
+        let ratio = (-cents / 1200.0).exp2();
+        let mut scaler = PeriodScaler::new(ratio);
+        self.scale_periods(&mut scaler, options.noise);
+        self.scale_timers(&mut scaler, options.digi_drums);
+        scaler.into_report()
+    }
+
+    fn scale_timers(&mut self, scaler: &mut PeriodScaler, digi_drums: bool) {
+        let version = self.version;
+        for (index, frame) in self.frames.iter_mut().enumerate() {
+            match version {
+                YmVersion::Ym2 => {
+                    if digi_drums && frame.data[VOL_C_REG as usize] & 0x80 == 0x80 {
+                        let divisor = frame.data[ENV_PER_COARSE_REG as usize].into();
+                        let divisor = scaler.scale(index, PeriodTarget::Timer(FxType::DigiDrum),
+                                                   divisor, MAX_YM2_DD_DIVISOR);
+                        frame.data[ENV_PER_COARSE_REG as usize] = divisor as u8;
+                    }
+                }
+                YmVersion::Ym3 => {}
+                YmVersion::Ym4|
+                YmVersion::Ym5 => {
+                    if frame.fx0().ts_channel().is_some() {
+                        scale_frame_timer(scaler, index, frame, FxType::SidVoice, 0);
+                    }
+                    if digi_drums && frame.fx1().dd_channel().is_some() {
+                        scale_frame_timer(scaler, index, frame, FxType::DigiDrum, 1);
+                    }
+                }
+                YmVersion::Ym6 => {
+                    for (timer, flags) in [frame.fx0(), frame.fx1()].into_iter().enumerate() {
+                        match flags.fx6_channel() {
+                            Some((FxType::DigiDrum, _)) if !digi_drums => {}
+                            Some((fx, _)) => scale_frame_timer(scaler, index, frame, fx, timer),
+                            None => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn scale_periods(&mut self, scaler: &mut PeriodScaler, noise: bool) {
+        let max_env_period = match self.version {
+            YmVersion::Ym2 => MAX_YM2_ENV_PERIOD,
+            _ => MAX_ENV_PERIOD
+        };
+        for (index, frame) in self.frames.iter_mut().enumerate() {
+            for chan in 0..3 {
+                let period = frame.tone_period(chan).into();
+                let period = scaler.scale(index, PeriodTarget::Tone(chan), period, MAX_TONE_PERIOD);
+                frame.set_tone_period(chan, period as u16);
+            }
+            if noise {
+                let period = frame.noise_period().into();
+                let period = scaler.scale(index, PeriodTarget::Noise, period, MAX_NOISE_PERIOD);
+                frame.set_noise_period(period as u8);
+            }
+            if self.version == YmVersion::Ym2 {
+                let period = frame.data[ENV_PER_FINE_REG as usize].into();
+                let period = scaler.scale(index, PeriodTarget::Envelope, period, max_env_period);
+                frame.data[ENV_PER_FINE_REG as usize] = period as u8;
+            }
+            else {
+                let period = frame.env_period().into();
+                let period = scaler.scale(index, PeriodTarget::Envelope, period, max_env_period);
+                frame.set_env_period(period as u16);
+            }
+        }
+    }
+}
+
+/// Scales the timer divisor of the special effect `fx0` if `timer` is `0` or `fx1` otherwise.
+fn scale_frame_timer(scaler: &mut PeriodScaler, index: usize, frame: &mut YmFrame, fx: FxType, timer: usize) {
+    let (prediv_reg, div_reg) = match timer {
+        0 => (NOISE_PER_REG as usize, 14),
+        _ => (VOL_A_REG as usize, 15)
+    };
+    let (prediv3, div8) = scaler.scale_timer(index, fx, frame.data[prediv_reg], frame.data[div_reg]);
+    frame.data[prediv_reg] = prediv3;
+    frame.data[div_reg] = div8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone_song(periods: &[u16]) -> YmSong {
+        let frames = periods.iter().map(|&period| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(0, period);
+            frame.data[1] |= 0x10;
+            frame
+        }).collect();
+        YmSong::new(YmVersion::Ym5, frames, 0, String::new(), None)
+    }
+
+    #[test]
+    fn retune_to_clock_works() {
+        let mut song = tone_song(&[284, 284, 3000, 1]);
+        let report = song.retune_to_clock(1_000_000);
+        assert_eq!(song.chipset_frequency, 1_000_000);
+        let periods: Vec<_> = song.frames.iter().map(|f| f.tone_period(0)).collect();
+        assert_eq!(periods, [142, 142, 1500, 1]);
+        assert_eq!(song.frames[0].data[1] & 0xf0, 0x10);
+        assert_eq!(report.inexact.len(), 1);
+        let inexact = &report.inexact[0];
+        assert_eq!(inexact.target, PeriodTarget::Tone(0));
+        assert_eq!((inexact.original, inexact.actual, inexact.first_frame, inexact.frames), (1, 1, 3, 1));
+        assert!(!inexact.is_clamped());
+
+        let report = song.retune_to_clock(4_000_000);
+        assert_eq!(report.clamped().count(), 1);
+        assert_eq!(song.frames[2].tone_period(0), 0x0fff);
+        assert_eq!(song.frames[0].tone_period(0), 568);
+    }
+
+    #[test]
+    fn transpose_works() {
+        let mut song = tone_song(&[1000, 0x0800]);
+        song.version = YmVersion::Ym6;
+        for frame in song.frames.iter_mut() {
+            frame.set_env_period(200);
+            frame.set_noise_period(10);
+            // SID voice on channel A, timer: 10 * 50
+            frame.data[6] |= 0b010_00000;
+            frame.data[14] = 50;
+        }
+        let report = song.transpose(12, TransposeOptions::default());
+        assert_eq!(song.frames[0].tone_period(0), 500);
+        assert_eq!(song.frames[0].env_period(), 100);
+        assert_eq!(song.frames[0].noise_period(), 10);
+        assert_eq!(song.frames[1].timer_divisor0().unwrap().get(), 250);
+        assert_eq!(song.frames[1].data[6] & 0xe0, 0b010_00000);
+        assert!(report.is_exact());
+
+        let report = song.transpose(-24, TransposeOptions { noise: true, digi_drums: false });
+        assert_eq!(song.frames[0].tone_period(0), 2000);
+        assert_eq!(song.frames[1].tone_period(0), 0x0fff);
+        assert_eq!(song.frames[0].noise_period(), 31);
+        assert_eq!(song.frames[0].timer_divisor0().unwrap().get(), 1000);
+        assert_eq!(song.frames[0].data[6] & 0xe0, 0b010_00000);
+        assert_eq!(report.clamped().map(|p| p.target).collect::<Vec<_>>(),
+                   [PeriodTarget::Noise, PeriodTarget::Tone(0)]);
+
+        let report = song.transpose_cents(37.0, TransposeOptions::default());
+        let timer = report.inexact.iter().find(|p| p.target == PeriodTarget::Timer(FxType::SidVoice)).unwrap();
+        assert!((timer.wanted - timer.actual as f64).abs() > 1.0);
+        assert!(!timer.is_clamped());
+        assert_eq!(report.clamped().count(), 0);
+    }
+}
@@ -0,0 +1,470 @@
+//! VGM file conversion.
+use std::io::{self, Read};
+
+use super::*;
+use super::psg::clear_unused_bits;
+
+/// How [YmSong::parse_vgm] handles files with two AY8910 chips.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DualChipMode {
+    /// Files with two chips are rejected.
+    #[default]
+    Reject,
+    /// Only the first chip is imported.
+    First,
+    /// Only the second chip is imported.
+    Second,
+    /// The louder voice of each pair of the chips' voices with the same name is imported.
+    Downmix,
+}
+
+/// The sample rate of all VGM files.
+const VGM_SAMPLE_RATE: u32 = 44_100;
+const VGM_VERSION: u32 = 0x0000_0151;
+const VGM_HEADER_SIZE: usize = 0x80;
+const GD3_VERSION: u32 = 0x0000_0100;
+const AY8910_CHIP_TYPE: u8 = 0x00;
+const YM2149_CHIP_TYPE: u8 = 0x10;
+/// Legacy output.
+const AY8910_FLAGS: u8 = 0x01;
+
+const CMD_AY8910: u8 = 0xa0;
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_735: u8 = 0x62;
+const CMD_WAIT_882: u8 = 0x63;
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_END: u8 = 0x66;
+const CMD_DATA_BLOCK: u8 = 0x67;
+const CMD_PCM_RAM_WRITE: u8 = 0x68;
+/// Set in the AY8910 clock for two chips.
+const DUAL_CHIP_BIT: u32 = 0x4000_0000;
+/// Set in the AY8910 register number for the second chip.
+const SECOND_CHIP_REG_BIT: u8 = 0x80;
+
+/// The masks of the AY/YM register bits used by the chipset.
+const AY_REG_MASKS: [u8;14] = [
+    0xff, 0x0f, 0xff, 0x0f, 0xff, 0x0f, 0x1f, 0x3f, 0x1f, 0x1f, 0x1f, 0xff, 0xff, 0x0f
+];
+
+impl YmSong {
+    /// Converts the song to the VGM 1.51 format with the AY8910 chip commands.
+    ///
+    /// All the frames are played once and then the frames from the [YmSong::loop_frame] are
+    /// played `loops` more times. The VGM loop offset is set at the loop frame of the last
+    /// repetition.
+    ///
+    /// The register changes are written at their exact sample positions, so the special effects
+    /// are preserved as the volume and envelope writes produced by the player. The chip type in
+    /// the header is taken from [YmSong::chip_type]. The GD3 tag is filled with the song's title,
+    /// program, author, year and comments.
+    pub fn to_vgm(&self, loops: u32) -> Vec<u8> {
+        let nframes = self.frames.len() as u64;
+        let loop_frame = (self.loop_frame as u64).min(nframes.saturating_sub(1));
+        let loop_len = nframes - loop_frame;
+        let total_frames = nframes + loops as u64 * loop_len;
+        let loop_start = match loops {
+            0 => loop_frame,
+            loops => nframes + (loops as u64 - 1) * loop_len
+        };
+
+        let mut song = self.clone();
+        song.reset();
+        let frame_frequency = self.frame_frequency.max(1) as u64;
+        let sample_per_cycle = VGM_SAMPLE_RATE as f64 / self.clock_frequency() as f64;
+        let frame_sample = |index: u64| index * VGM_SAMPLE_RATE as u64 / frame_frequency;
+
+        let mut out = vec![0u8;VGM_HEADER_SIZE];
+        let mut regs: [Option<u8>;14] = [None;14];
+        let mut sample = 0u64;
+        let mut loop_offset = None;
+        for index in 0..total_frames {
+            if index == loop_start {
+                write_vgm_wait(&mut out, &mut sample, frame_sample(index));
+                loop_offset = Some((out.len(), sample));
+                // the looped part must set up all registers
+                regs = [None;14];
+            }
+            let start = frame_sample(index);
+            let end = frame_sample(index + 1).max(start + 1);
+            song.produce_next_ay_frame(|ts, reg, val| {
+                let reg = reg as usize;
+                let val = val & AY_REG_MASKS[reg];
+                if reg != ENV_REG as usize && regs[reg] == Some(val) {
+                    return
+                }
+                regs[reg] = Some(val);
+                let at = (start + (ts as f64 * sample_per_cycle) as u64).min(end - 1);
+                write_vgm_wait(&mut out, &mut sample, at);
+                out.extend_from_slice(&[CMD_AY8910, reg as u8, val]);
+            });
+        }
+        write_vgm_wait(&mut out, &mut sample, frame_sample(total_frames));
+        out.push(CMD_END);
+
+        let gd3_offset = out.len();
+        self.write_gd3(&mut out);
+
+        let eof = out.len();
+        let mut put = |offset: usize, value: u32| {
+            out[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        put(0x04, (eof - 0x04) as u32);
+        put(0x08, VGM_VERSION);
+        put(0x14, (gd3_offset - 0x14) as u32);
+        put(0x18, sample as u32);
+        if let Some((offset, loop_sample)) = loop_offset.filter(|_| total_frames != 0) {
+            put(0x1c, (offset - 0x1c) as u32);
+            put(0x20, (sample - loop_sample) as u32);
+        }
+        put(0x24, self.frame_frequency as u32);
+        put(0x34, (VGM_HEADER_SIZE - 0x34) as u32);
+        put(0x74, self.chipset_frequency);
+        put(0x00, u32::from_le_bytes(*b"Vgm "));
+        out[0x78] = match self.chip_type {
+            ChipType::Ay => AY8910_CHIP_TYPE,
+            ChipType::Ym => YM2149_CHIP_TYPE
+        };
+        out[0x79] = AY8910_FLAGS;
+        out
+    }
+
+    /// Attempts to parse a VGM file with the AY8910 chip commands from the given stream source.
+    ///
+    /// The register state is sampled at the given `frame_frequency` into `YM6!` frames without
+    /// special effects. The envelope shape register is set in every frame it was written in,
+    /// otherwise it's set to `0xff`. The loop frame is taken from the VGM loop offset, and the
+    /// title, program, author, year and comments from the GD3 tag.
+    ///
+    /// The commands of other chips are ignored. Files with two AY8910 chips are handled according
+    /// to `dual_chip`. Compressed (`VGZ`) files are not supported.
+    pub fn parse_vgm<R: Read>(
+            mut rd: R,
+            frame_frequency: u16,
+            dual_chip: DualChipMode
+        ) -> io::Result<YmSong>
+    {
+        if frame_frequency == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frequency must not be 0"))
+        }
+        let mut data = Vec::new();
+        rd.read_to_end(&mut data)?;
+        if data.len() < 0x40 || &data[..4] != b"Vgm " {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognized file signature"))
+        }
+        let dword = |offset: usize| {
+            data.get(offset..offset + 4).map_or(0, |b| u32::from_le_bytes(b.try_into().unwrap())) as usize
+        };
+        let version = dword(0x08);
+        let data_start = match dword(0x34) {
+            offset if version >= 0x150 && offset != 0 => 0x34 + offset,
+            _ => 0x40
+        };
+        let clock = if version >= 0x151 && data_start > 0x78 { dword(0x74) as u32 } else { 0 };
+        let chipset_frequency = clock & !(DUAL_CHIP_BIT | 0x8000_0000);
+        if chipset_frequency == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no AY8910 chip"))
+        }
+        let dual = clock & DUAL_CHIP_BIT != 0;
+        if dual && dual_chip == DualChipMode::Reject {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "two AY8910 chips"))
+        }
+        let chip_type = match data.get(0x78) {
+            Some(0x10..=0x13) => ChipType::Ym,
+            _ => ChipType::Ay
+        };
+        let loop_pos = match dword(0x1c) {
+            0 => None,
+            offset => Some(0x1c + offset)
+        };
+
+        let ff = frame_frequency as u64;
+        let mut frames = Vec::new();
+        let mut chips = [ChipState::default();2];
+        let mut sample = 0u64;
+        let mut loop_sample = None;
+        let next_frame = |sample: u64, chips: &mut [ChipState;2], frames: &mut Vec<YmFrame>| {
+            while (frames.len() as u64 + 1) * VGM_SAMPLE_RATE as u64 <= sample * ff {
+                let [first, second] = chips;
+                frames.push(match (dual, dual_chip) {
+                    (true, DualChipMode::Second) => second.take_frame(),
+                    (true, DualChipMode::Downmix) => downmix_frames(first, second),
+                    _ => first.take_frame()
+                });
+            }
+        };
+        let mut pos = data_start;
+        while let Some(&cmd) = data.get(pos) {
+            if Some(pos) == loop_pos {
+                loop_sample = Some(sample);
+            }
+            let operand = |index: usize| data.get(pos + index).copied().unwrap_or(0);
+            let wait = match cmd {
+                CMD_END => break,
+                CMD_AY8910 => {
+                    let (reg, val) = (operand(1), operand(2));
+                    let chip = &mut chips[(reg & SECOND_CHIP_REG_BIT != 0) as usize];
+                    match reg & !SECOND_CHIP_REG_BIT {
+                        ENV_REG => chip.shape = Some(val & 0x0f),
+                        reg @ 0..=12 => chip.regs[reg as usize] = val,
+                        _ => {}
+                    }
+                    0
+                }
+                CMD_WAIT => u16::from_le_bytes([operand(1), operand(2)]) as u64,
+                CMD_WAIT_735 => 735,
+                CMD_WAIT_882 => 882,
+                0x70..=0x7f => (cmd & 0x0f) as u64 + 1,
+                0x80..=0x8f => (cmd & 0x0f) as u64,
+                _ => 0
+            };
+            let len = match cmd {
+                CMD_DATA_BLOCK => 7 + (dword(pos + 3) & 0x7fff_ffff),
+                cmd => vgm_command_len(cmd).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, "unknown VGM command")
+                })?
+            };
+            if wait != 0 {
+                sample += wait;
+                next_frame(sample, &mut chips, &mut frames);
+            }
+            pos += len;
+        }
+        // the last partial frame
+        if !(sample * ff).is_multiple_of(VGM_SAMPLE_RATE as u64) {
+            next_frame(sample + VGM_SAMPLE_RATE as u64 / ff, &mut chips, &mut frames);
+        }
+        if frames.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "no frames"))
+        }
+        let loop_frame = loop_sample.map_or(0, |sample| {
+            ((sample * ff + VGM_SAMPLE_RATE as u64 / 2) / VGM_SAMPLE_RATE as u64)
+            .min(frames.len() as u64 - 1) as u32
+        });
+
+        let mut texts = read_gd3(&data, 0x14 + dword(0x14)).filter(|_| dword(0x14) != 0)
+                        .unwrap_or_default().into_iter();
+        let mut text = || texts.next().unwrap_or_default();
+        let (title, title_jp, program, _, _, _, author, author_jp, date, _, comments) = (
+            text(), text(), text(), text(), text(), text(), text(), text(), text(), text(), text()
+        );
+        let or_jp = |en: String, jp: String| if en.is_empty() { jp } else { en };
+        let mut song = YmSong::new(YmVersion::Ym6, frames.into_boxed_slice(), loop_frame,
+                                   or_jp(title, title_jp), None)
+                              .with_meta(or_jp(author, author_jp), comments)
+                              .with_frequency(chipset_frequency, frame_frequency);
+        song.program = program;
+        song.year = date.get(..4).and_then(|year| year.parse().ok()).unwrap_or(0);
+        song.chip_type = chip_type;
+        Ok(song)
+    }
+
+    fn write_gd3(&self, out: &mut Vec<u8>) {
+        let year = match self.year {
+            0 => String::new(),
+            year => year.to_string()
+        };
+        let texts: [&str;11] = [
+            &self.title, "", &self.program, "", "", "", &self.author, "", &year, "", &self.comments
+        ];
+        let mut data = Vec::new();
+        for text in texts {
+            for unit in text.encode_utf16().chain(Some(0)) {
+                data.extend_from_slice(&unit.to_le_bytes());
+            }
+        }
+        out.extend_from_slice(b"Gd3 ");
+        out.extend_from_slice(&GD3_VERSION.to_le_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&data);
+    }
+}
+
+/// The register state of a single AY8910 chip.
+#[derive(Debug, Default, Clone, Copy)]
+struct ChipState {
+    regs: [u8;14],
+    /// The envelope shape written in the current frame.
+    shape: Option<u8>,
+}
+
+impl ChipState {
+    /// Creates the frame from the current state and starts the next frame.
+    fn take_frame(&mut self) -> YmFrame {
+        let mut frame = YmFrame::default();
+        frame.data[..14].copy_from_slice(&self.regs);
+        clear_unused_bits(&mut frame.data);
+        frame.data[ENV_REG as usize] = self.shape.take().unwrap_or(0xff);
+        frame
+    }
+
+    /// Returns the loudness of the voice channel `chan` and whether it uses the envelope.
+    fn level(&self, chan: usize) -> (u8, bool) {
+        let vol = self.regs[VOL_A_REG as usize + chan] & 0x1f;
+        let mixer = self.regs[MIXER_REG as usize];
+        let env = vol & 0x10 != 0;
+        let level = if env { 16 } else { vol & 0x0f };
+        if mixer & (0b001001 << chan) == (0b001001 << chan) && !env {
+            return (0, false)
+        }
+        (level, env)
+    }
+}
+
+/// Mixes the frames of two chips, taking the louder voice of each pair.
+fn downmix_frames(first: &mut ChipState, second: &mut ChipState) -> YmFrame {
+    let levels: [[(u8, bool);3];2] = [
+        [0, 1, 2].map(|chan| first.level(chan)),
+        [0, 1, 2].map(|chan| second.level(chan))
+    ];
+    let mut frame = first.take_frame();
+    let other = second.take_frame();
+    let first_env = levels[0].iter().any(|&(_, env)| env);
+    for (chan, (&(level, env), &(first_level, _))) in levels[1].iter().zip(levels[0].iter()).enumerate() {
+        if level <= first_level {
+            continue
+        }
+        let reg = chan * 2;
+        frame.data[reg..reg + 2].copy_from_slice(&other.data[reg..reg + 2]);
+        frame.data[VOL_A_REG as usize + chan] = other.data[VOL_A_REG as usize + chan];
+        let mask = 0b001001 << chan;
+        let mixer = &mut frame.data[MIXER_REG as usize];
+        *mixer = (*mixer & !mask) | (other.data[MIXER_REG as usize] & mask);
+        if other.data[MIXER_REG as usize] & (8 << chan) == 0 {
+            frame.set_noise_period(other.noise_period());
+        }
+        if env && !first_env {
+            for reg in ENV_PER_FINE_REG..=ENV_REG {
+                frame.data[reg as usize] = other.data[reg as usize];
+            }
+        }
+    }
+    frame
+}
+
+/// Returns the length of the VGM command `cmd` including the command byte, or `None` if the
+/// command is unknown or has a variable length.
+fn vgm_command_len(cmd: u8) -> Option<usize> {
+    Some(match cmd {
+        0x30..=0x3f|0x4f|0x50|0x94 => 2,
+        0x40..=0x4e|0x51..=0x5f|0xa0..=0xbf|0x61 => 3,
+        0x62|0x63|0x66|0x70..=0x8f => 1,
+        0xc0..=0xdf => 4,
+        0xe0..=0xff|0x90|0x91|0x95 => 5,
+        0x92 => 6,
+        0x93 => 11,
+        CMD_PCM_RAM_WRITE => 12,
+        _ => return None
+    })
+}
+
+/// Reads the UTF-16 strings of the GD3 tag at `offset`.
+fn read_gd3(data: &[u8], offset: usize) -> Option<Vec<String>> {
+    let tag = data.get(offset..offset + 12)?;
+    if &tag[..4] != b"Gd3 " {
+        return None
+    }
+    let len = u32::from_le_bytes(tag[8..12].try_into().unwrap()) as usize;
+    let text = data.get(offset + 12..)?;
+    let text = &text[..len.min(text.len())];
+    let units: Vec<u16> = text.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+    Some(units.split(|&unit| unit == 0).map(String::from_utf16_lossy).collect())
+}
+
+/// Writes the wait commands advancing the `sample` position to `target`.
+fn write_vgm_wait(out: &mut Vec<u8>, sample: &mut u64, target: u64) {
+    let mut wait = target.saturating_sub(*sample);
+    *sample += wait;
+    while wait != 0 {
+        let step = wait.min(u16::MAX as u64);
+        match step {
+            735 => out.push(CMD_WAIT_735),
+            882 => out.push(CMD_WAIT_882),
+            1..=16 => out.push(CMD_WAIT_SHORT + step as u8 - 1),
+            _ => {
+                out.push(CMD_WAIT);
+                out.extend_from_slice(&(step as u16).to_le_bytes());
+            }
+        }
+        wait -= step;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_vgm_works() {
+        let frames = [284u16, 284, 190].iter().map(|&period| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(0, period);
+            frame.data[MIXER_REG as usize] = 0b111110;
+            frame.data[VOL_A_REG as usize] = 15;
+            frame.data[ENV_REG as usize] = 0xff;
+            frame
+        }).collect();
+        let song = YmSong::new(YmVersion::Ym5, frames, 1, "Title".into(), None);
+        let vgm = song.to_vgm(1);
+        let dword = |offset: usize| u32::from_le_bytes(vgm[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&vgm[..4], b"Vgm ");
+        assert_eq!(dword(0x04) as usize, vgm.len() - 4);
+        assert_eq!(dword(0x08), 0x151);
+        assert_eq!(dword(0x74), 2_000_000);
+        assert_eq!(vgm[0x78], YM2149_CHIP_TYPE);
+        // 5 frames of 882 samples, 2 of them looping
+        assert_eq!(dword(0x18), 5 * 882);
+        assert_eq!(dword(0x20), 2 * 882);
+        // the player starts with the envelope period registers
+        assert_eq!(&vgm[0x80..0x86], &[CMD_AY8910, 11, 0, CMD_AY8910, 12, 0]);
+        let loop_offset = 0x1c + dword(0x1c) as usize;
+        assert_eq!(vgm[loop_offset - 1], CMD_WAIT_882);
+        assert_eq!(&vgm[loop_offset..loop_offset + 6], &vgm[0x80..0x86]);
+        let gd3_offset = 0x14 + dword(0x14) as usize;
+        assert_eq!(&vgm[gd3_offset..gd3_offset + 4], b"Gd3 ");
+        assert_eq!(&vgm[gd3_offset + 12..gd3_offset + 16], &[b'T', 0, b'i', 0]);
+
+        let parsed = YmSong::parse_vgm(&vgm[..], 50, DualChipMode::Reject).unwrap();
+        assert_eq!(parsed.title, "Title");
+        assert_eq!(parsed.frames.len(), 5);
+        assert_eq!(parsed.loop_frame, 3);
+        let periods: Vec<_> = parsed.frames.iter().map(|f| f.tone_period(0)).collect();
+        assert_eq!(periods, [284, 284, 190, 284, 190]);
+        assert!(parsed.frames.iter().all(|f| f.data[ENV_REG as usize] == 0xff && f.vol(0) == 15));
+    }
+
+    #[test]
+    fn parse_vgm_dual_chip_works() {
+        let mut vgm = vec![0u8;VGM_HEADER_SIZE];
+        vgm[..4].copy_from_slice(b"Vgm ");
+        vgm[0x08..0x0c].copy_from_slice(&VGM_VERSION.to_le_bytes());
+        vgm[0x34..0x38].copy_from_slice(&((VGM_HEADER_SIZE - 0x34) as u32).to_le_bytes());
+        vgm[0x74..0x78].copy_from_slice(&(1_789_772 | DUAL_CHIP_BIT).to_le_bytes());
+        vgm.extend_from_slice(&[CMD_AY8910, VOL_A_REG, 5,
+                                CMD_AY8910, VOL_A_REG | SECOND_CHIP_REG_BIT, 9,
+                                CMD_AY8910, SECOND_CHIP_REG_BIT, 100,
+                                CMD_AY8910, ENV_REG | SECOND_CHIP_REG_BIT, 0x0e,
+                                CMD_WAIT_882, CMD_END]);
+        assert!(YmSong::parse_vgm(&vgm[..], 50, DualChipMode::Reject).is_err());
+        let first = YmSong::parse_vgm(&vgm[..], 50, DualChipMode::First).unwrap();
+        assert_eq!((first.frames[0].vol(0), first.frames[0].data[ENV_REG as usize]), (5, 0xff));
+        let mixed = YmSong::parse_vgm(&vgm[..], 50, DualChipMode::Downmix).unwrap();
+        assert_eq!(mixed.chipset_frequency, 1_789_772);
+        assert_eq!((mixed.frames[0].vol(0), mixed.frames[0].tone_period(0)), (9, 100));
+    }
+
+    #[test]
+    fn parse_vgm_skips_stream_commands() {
+        let mut vgm = vec![0u8;VGM_HEADER_SIZE];
+        vgm[..4].copy_from_slice(b"Vgm ");
+        vgm[0x08..0x0c].copy_from_slice(&VGM_VERSION.to_le_bytes());
+        vgm[0x34..0x38].copy_from_slice(&((VGM_HEADER_SIZE - 0x34) as u32).to_le_bytes());
+        vgm[0x74..0x78].copy_from_slice(&2_000_000u32.to_le_bytes());
+        vgm.extend_from_slice(&[0x90, 0x00, 0x02, 0x00, 0x2a,
+                                0x92, 0x00, 0x44, 0xac, 0x00, 0x00,
+                                CMD_AY8910, VOL_A_REG, 7,
+                                CMD_AY8910, 0, 100,
+                                CMD_WAIT_882, CMD_END]);
+        let song = YmSong::parse_vgm(&vgm[..], 50, DualChipMode::Reject).unwrap();
+        assert_eq!((song.frames[0].vol(0), song.frames[0].tone_period(0)), (7, 100));
+    }
+}
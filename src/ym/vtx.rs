@@ -0,0 +1,169 @@
+//! VTX (Vortex) file conversion.
+use std::io::{self, Read, Write};
+
+use delharc::decode::{Decoder, Lh5Decoder};
+
+use super::*;
+use super::psg::clear_unused_bits;
+
+/// The size of the fixed part of the header.
+const VTX_HEADER_SIZE: usize = 16;
+/// The number of registers of a single frame in the register dump.
+const VTX_REGS: usize = 14;
+
+impl YmSong {
+    /// Attempts to parse a `VTX` file from the given stream source.
+    ///
+    /// The new song is a `YM6!` song without special effects. The title, author, program,
+    /// tracker, comment, year, chip type, stereo layout, loop and frequencies are taken from the
+    /// file's header.
+    pub fn parse_vtx<R: Read>(mut rd: R) -> io::Result<YmSong> {
+        let mut data = Vec::new();
+        rd.read_to_end(&mut data)?;
+        if data.len() < VTX_HEADER_SIZE {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely"))
+        }
+        let chip_type = match &data[..2] {
+            b"ay"|b"AY" => ChipType::Ay,
+            b"ym"|b"YM" => ChipType::Ym,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unrecognized file signature"))
+        };
+        let stereo = match data[2] {
+            0 => StereoLayout::Mono,
+            1 => StereoLayout::Abc,
+            2 => StereoLayout::Acb,
+            3 => StereoLayout::Bac,
+            4 => StereoLayout::Bca,
+            5 => StereoLayout::Cab,
+            6 => StereoLayout::Cba,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid stereo mode"))
+        };
+        let loop_frame = u16::from_le_bytes([data[3], data[4]]) as u32;
+        let chipset_frequency = u32::from_le_bytes(data[5..9].try_into().unwrap());
+        let frame_frequency = match data[9] {
+            0 => DEFAULT_FRAME_FREQUENCY,
+            freq => freq as u16
+        };
+        let year = u16::from_le_bytes([data[10], data[11]]);
+        let size = u32::from_le_bytes(data[12..16].try_into().unwrap()) as usize;
+        if chipset_frequency == 0 || size == 0 || !size.is_multiple_of(VTX_REGS) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid VTX header"))
+        }
+        let mut rest = &data[VTX_HEADER_SIZE..];
+        let mut texts: [String;5] = Default::default();
+        for text in texts.iter_mut() {
+            let len = rest.iter().position(|&b| b == 0).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "file ended prematurely")
+            })?;
+            *text = String::from_utf8_lossy(&rest[..len]).into_owned();
+            rest = &rest[len + 1..];
+        }
+        if size > rest.len().saturating_mul(lh5::MAX_RATIO) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid VTX header"))
+        }
+        let mut dump = vec![0u8;size];
+        Lh5Decoder::new(rest).fill_buffer(&mut dump)?;
+        let nframes = size / VTX_REGS;
+        let frames = (0..nframes).map(|index| {
+            let mut frame = YmFrame::default();
+            for (reg, val) in frame.data[..VTX_REGS].iter_mut().enumerate() {
+                *val = dump[reg * nframes + index];
+            }
+            clear_unused_bits(&mut frame.data);
+            frame
+        }).collect();
+        let [title, author, program, tracker, comments] = texts;
+        let mut song = YmSong::new(YmVersion::Ym6, frames, loop_frame, title, None)
+                              .with_meta(author, comments)
+                              .with_frequency(chipset_frequency, frame_frequency);
+        song.program = program;
+        song.tracker = tracker;
+        song.year = year;
+        song.chip_type = chip_type;
+        song.stereo = stereo;
+        Ok(song)
+    }
+
+    /// Writes the song as a `VTX` file to the given stream.
+    ///
+    /// The YM-specific special effects are dropped, the same way as in [YmSong::write_psg].
+    ///
+    /// Returns an error if the loop frame doesn't fit in 16 bits or the frame frequency doesn't
+    /// fit in 8 bits.
+    pub fn write_vtx<W: Write>(&self, mut wr: W) -> io::Result<()> {
+        let loop_frame = u16::try_from(self.loop_frame).ok();
+        let frame_frequency = u8::try_from(self.frame_frequency).ok().filter(|&freq| freq != 0);
+        let (Some(loop_frame), Some(frame_frequency)) = (loop_frame, frame_frequency) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "song can't be represented in VTX"))
+        };
+        let nframes = self.frames.len();
+        let mut dump = vec![0u8;nframes * VTX_REGS];
+        for (index, regs) in self.ay_register_dump().into_iter().enumerate() {
+            for (reg, val) in regs.into_iter().enumerate() {
+                dump[reg * nframes + index] = val;
+            }
+        }
+        let size = u32::try_from(dump.len())
+                   .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let mut header = Vec::with_capacity(VTX_HEADER_SIZE);
+        header.extend_from_slice(match self.chip_type {
+            ChipType::Ay => b"ay",
+            ChipType::Ym => b"ym"
+        });
+        header.push(self.stereo as u8);
+        header.extend_from_slice(&loop_frame.to_le_bytes());
+        header.extend_from_slice(&self.chipset_frequency.to_le_bytes());
+        header.push(frame_frequency);
+        header.extend_from_slice(&self.year.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        for text in [&self.title, &self.author, &self.program, &self.tracker, &self.comments] {
+            let bytes = text.as_bytes();
+            let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+            header.extend_from_slice(&bytes[..len]);
+            header.push(0);
+        }
+        wr.write_all(&header)?;
+        wr.write_all(&lh5::compress(&dump))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vtx_roundtrip_works() {
+        let frames = (0..300u32).map(|n| {
+            let mut frame = YmFrame::default();
+            frame.set_tone_period(0, (n % 24) as u16 * 10 + 100);
+            frame.data[VOL_A_REG as usize] = (n % 16) as u8;
+            frame.data[ENV_REG as usize] = if n % 32 == 0 { 0x0e } else { 0xff };
+            frame
+        }).collect();
+        let mut song = YmSong::new(YmVersion::Ym6, frames, 12, "Title".into(), None)
+                              .with_meta("Author".into(), "Comment".into())
+                              .with_frequency(1_773_400, 50);
+        song.program = "Game".into();
+        song.tracker = "Vortex Tracker".into();
+        song.year = 1999;
+        song.chip_type = ChipType::Ay;
+        song.stereo = StereoLayout::Acb;
+        let mut vtx = Vec::new();
+        song.write_vtx(&mut vtx).unwrap();
+        assert!(vtx.starts_with(b"ay\x02\x0c\x00"));
+        let parsed = YmSong::parse_vtx(&vtx[..]).unwrap();
+        assert_eq!(parsed.loop_frame, 12);
+        assert_eq!((parsed.chipset_frequency, parsed.frame_frequency), (1_773_400, 50));
+        assert_eq!((parsed.year, parsed.chip_type, parsed.stereo), (1999, ChipType::Ay, StereoLayout::Acb));
+        assert_eq!([&parsed.title, &parsed.author, &parsed.program, &parsed.tracker, &parsed.comments],
+                   ["Title", "Author", "Game", "Vortex Tracker", "Comment"]);
+        assert_eq!(parsed.frames.len(), 300);
+        for (a, b) in parsed.frames.iter().zip(song.frames.iter()) {
+            assert_eq!(a.data, b.data);
+        }
+        // the unpacked size exceeding the limit of the packed data
+        vtx[12..16].copy_from_slice(&(u32::MAX - 3).to_le_bytes());
+        assert_eq!(YmSong::parse_vtx(&vtx[..]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,117 @@
+use std::io::{self, Write};
+
+use super::*;
+
+impl YmSong {
+    /// Writes the song as an uncompressed YM-file to the given stream.
+    ///
+    /// The file format is determined by the song's [YmSong::version]. A `YM3!` song with a
+    /// non-zero `loop_frame` is written as `YM3b`.
+    ///
+    /// The `DIGI-DRUM` samples are written as 4-bit and the frames are always interleaved.
+    pub fn write_unpacked<W: Write>(&self, wr: W) -> io::Result<()> {
+        let mut wr = io::BufWriter::new(wr);
+        match self.version {
+            YmVersion::Ym2 => {
+                wr.write_all(b"YM2!")?;
+                write_interleaved_frames(&self.frames, 14, wr.by_ref())?;
+            }
+            YmVersion::Ym3 => {
+                if self.loop_frame == 0 {
+                    wr.write_all(b"YM3!")?;
+                    write_interleaved_frames(&self.frames, 14, wr.by_ref())?;
+                }
+                else {
+                    wr.write_all(b"YM3b")?;
+                    write_interleaved_frames(&self.frames, 14, wr.by_ref())?;
+                    wr.write_all(&self.loop_frame.to_be_bytes())?;
+                }
+            }
+            YmVersion::Ym4|YmVersion::Ym5|YmVersion::Ym6 => self.write_ym4_or_later(wr.by_ref())?
+        }
+        wr.flush()
+    }
+
+    fn write_ym4_or_later<W: Write>(&self, mut wr: W) -> io::Result<()> {
+        let nframes = u32::try_from(self.frames.len())
+                      .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let nsamples = self.dd_samples_ends.iter().rposition(|&end| end != 0).map_or(0, |n| n + 1);
+        let song_attrs = (self.song_attrs | SongAttributes::INTERLEAVED | SongAttributes::DIGIDRUM_4BIT)
+                         - SongAttributes::DIGIDRUM_SIGNED;
+        wr.write_all(self.version.tag().as_bytes())?;
+        wr.write_all(b"LeOnArD!")?;
+        wr.write_all(&nframes.to_be_bytes())?;
+        wr.write_all(&song_attrs.bits().to_be_bytes())?;
+        wr.write_all(&(nsamples as u16).to_be_bytes())?;
+        if self.version != YmVersion::Ym4 {
+            wr.write_all(&self.chipset_frequency.to_be_bytes())?;
+            wr.write_all(&self.frame_frequency.to_be_bytes())?;
+        }
+        wr.write_all(&self.loop_frame.to_be_bytes())?;
+        if self.version != YmVersion::Ym4 {
+            wr.write_all(&0u16.to_be_bytes())?;
+        }
+        for sample in 0..nsamples {
+            let data = self.dd_samples.get(self.sample_data_range(sample)).unwrap_or(&[]);
+            wr.write_all(&(data.len() as u32).to_be_bytes())?;
+            wr.write_all(data)?;
+        }
+        for text in [&self.title, &self.author, &self.comments] {
+            write_cstr(text, wr.by_ref())?;
+        }
+        write_interleaved_frames(&self.frames, 16, wr.by_ref())?;
+        wr.write_all(b"End!")
+    }
+}
+
+fn write_interleaved_frames<W: Write>(frames: &[YmFrame], regs: usize, mut wr: W) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(frames.len());
+    for r in 0..regs {
+        buf.clear();
+        buf.extend(frames.iter().map(|frame| frame.data[r]));
+        wr.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+fn write_cstr<W: Write>(text: &str, mut wr: W) -> io::Result<()> {
+    let bytes = text.as_bytes();
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    wr.write_all(&bytes[..len])?;
+    wr.write_all(&[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_unpacked_works() {
+        let frames = (0..5u8).map(|n| YmFrame { data: [n;16] }).collect();
+        let mut dd_samples_ends = [0;MAX_DD_SAMPLES];
+        dd_samples_ends[..2].copy_from_slice(&[2, 5]);
+        for version in [YmVersion::Ym3, YmVersion::Ym4, YmVersion::Ym5, YmVersion::Ym6] {
+            let song = YmSong::new(version, Box::clone(&frames), 3, "Title".into(), None)
+                             .with_meta("Author".into(), "Comments".into())
+                             .with_samples(SongAttributes::default(), vec![1, 2, 3, 4, 5].into(), dd_samples_ends)
+                             .with_frequency(1_000_000, 60);
+            let mut buf = Vec::new();
+            song.write_unpacked(&mut buf).unwrap();
+            let parsed = YmSong::parse_unpacked(io::Cursor::new(buf), "file").unwrap();
+            assert_eq!(parsed.version, version);
+            assert_eq!(parsed.loop_frame, 3);
+            assert_eq!(parsed.frames.len(), 5);
+            if version == YmVersion::Ym3 {
+                assert_eq!(parsed.frames[4].data[..14], [4;14]);
+                continue
+            }
+            assert_eq!(parsed.frames[4].data, [4;16]);
+            assert_eq!(&parsed.dd_samples[..], &[1, 2, 3, 4, 5]);
+            assert_eq!(parsed.dd_samples_ends, dd_samples_ends);
+            assert_eq!((parsed.title.as_str(), parsed.author.as_str()), ("Title", "Author"));
+            if version != YmVersion::Ym4 {
+                assert_eq!((parsed.chipset_frequency, parsed.frame_frequency), (1_000_000, 60));
+            }
+        }
+    }
+}